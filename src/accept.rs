@@ -0,0 +1,135 @@
+//! content negotiation with the `Accept` header
+
+use crate::request::trim;
+
+/// a media range of `Accept` with its quality value
+struct MediaRange<'a> {
+    ty: &'a [u8],
+    subtype: &'a [u8],
+    q: f32,
+}
+
+fn media_ranges<'a>(values: impl Iterator<Item = &'a [u8]>) -> Vec<MediaRange<'a>> {
+    values
+        .flat_map(|v| v.split(|&b| b == b','))
+        .filter_map(|range| {
+            let mut params = range.split(|&b| b == b';');
+            let essence = trim(params.next().unwrap_or_default());
+            let slash = essence.iter().position(|&b| b == b'/')?;
+            let (ty, subtype) = (trim(&essence[..slash]), trim(&essence[slash + 1..]));
+            // `*/json` is not a valid range
+            if ty.is_empty() || subtype.is_empty() || (ty == b"*" && subtype != b"*") {
+                return None;
+            }
+            let q = params
+                .find_map(|p| {
+                    let p = trim(p);
+                    p.strip_prefix(b"q=").or_else(|| p.strip_prefix(b"Q="))
+                })
+                .map_or(1.0, parse_q);
+            Some(MediaRange { ty, subtype, q })
+        })
+        .collect()
+}
+
+/// the quality of a content type, it's the one of the most specific range that matches
+fn quality(ranges: &[MediaRange], content_type: &str) -> f32 {
+    // the parameters like `charset` are not compared
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    let (ty, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+    let mut best: Option<(u8, f32)> = None;
+    for range in ranges {
+        let specificity = if range.ty == b"*" {
+            0
+        } else if !range.ty.eq_ignore_ascii_case(ty.as_bytes()) {
+            continue;
+        } else if range.subtype == b"*" {
+            1
+        } else if range.subtype.eq_ignore_ascii_case(subtype.as_bytes()) {
+            2
+        } else {
+            continue;
+        };
+        if best.map_or(true, |(s, _)| specificity > s) {
+            best = Some((specificity, range.q));
+        }
+    }
+    best.map_or(0.0, |(_, q)| q)
+}
+
+/// pick the content type of the candidates with the highest quality value
+///
+/// ties are broken by the order of the candidates, no ranges accept the first
+pub(crate) fn preferred<'a, 'c>(
+    values: impl Iterator<Item = &'a [u8]>,
+    candidates: &[&'c str],
+) -> Option<&'c str> {
+    let ranges = media_ranges(values);
+    if ranges.is_empty() {
+        return candidates.first().copied();
+    }
+    let mut best = None;
+    let mut best_q = 0.0;
+    for candidate in candidates {
+        let q = quality(&ranges, candidate);
+        if q > best_q {
+            best = Some(*candidate);
+            best_q = q;
+        }
+    }
+    best
+}
+
+/// an invalid quality value is taken as not acceptable
+pub(crate) fn parse_q(q: &[u8]) -> f32 {
+    std::str::from_utf8(q)
+        .ok()
+        .and_then(|q| q.trim().parse::<f32>().ok())
+        .filter(|q| (0.0..=1.0).contains(q))
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preferred_of<'c>(accept: &str, candidates: &[&'c str]) -> Option<&'c str> {
+        preferred(std::iter::once(accept.as_bytes()), candidates)
+    }
+
+    #[test]
+    fn most_specific_range_wins() {
+        let accept = "text/*;q=0.5, text/html, */*;q=0.1";
+        assert_eq!(
+            preferred_of(accept, &["text/plain", "text/html"]),
+            Some("text/html")
+        );
+        assert_eq!(
+            preferred_of(accept, &["image/png", "text/plain"]),
+            Some("text/plain")
+        );
+        assert_eq!(preferred_of(accept, &["image/png"]), Some("image/png"));
+        let accept = "application/json;q=0, */*";
+        assert_eq!(preferred_of(accept, &["application/json"]), None);
+    }
+
+    #[test]
+    fn ties_keep_the_order_of_the_candidates() {
+        let candidates = ["application/json", "text/html; charset=utf-8"];
+        assert_eq!(preferred_of("*/*", &candidates), Some("application/json"));
+        assert_eq!(preferred_of("", &candidates), Some("application/json"));
+        assert_eq!(
+            preferred_of("TEXT/HTML", &candidates),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn invalid_ranges_are_skipped() {
+        assert_eq!(
+            preferred_of("*/json, text", &["application/json"]),
+            Some("application/json")
+        );
+        assert_eq!(preferred_of("text/html;q=2", &["text/html"]), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! the standard base64 alphabet with padding
+
+#[cfg(feature = "websocket")]
+pub(crate) fn encode(data: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity((data.len() / 3 + 1) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+        for (i, shift) in [18, 12, 6, 0].into_iter().enumerate() {
+            if i <= chunk.len() {
+                out.push(CHARS[n >> shift & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// `None` if the data has a character out of the alphabet, the padding is optional
+pub(crate) fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let data = data
+        .strip_suffix(b"==")
+        .or_else(|| data.strip_suffix(b"="))
+        .unwrap_or(data);
+    if data.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len() / 4 * 3 + 2);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            n |= (value(c)? as u32) << (18 - 6 * i);
+        }
+        out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn encode_pads() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn decode_with_and_without_padding() {
+        assert_eq!(decode(b"Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(decode(b"Zm9vYg").unwrap(), b"foob");
+        assert_eq!(decode(b"Zm9vYmE=").unwrap(), b"fooba");
+        assert_eq!(decode(b"Zm9v").unwrap(), b"foo");
+        assert_eq!(decode(b"").unwrap(), b"");
+    }
+
+    #[test]
+    fn decode_rejects_invalid_data() {
+        assert_eq!(decode(b"Zm9v*A=="), None);
+        assert_eq!(decode(b"Zm9vY"), None);
+        assert_eq!(decode(b"Zm=9"), None);
+    }
+}
@@ -0,0 +1,364 @@
+//! compression of the response body
+
+use std::io::{self, Write};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use crate::accept::parse_q;
+use crate::request::trim;
+
+/// the default size below which a body is sent uncompressed
+pub(crate) const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// a content encoding of the response body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Gzip,
+    /// the zlib format, like the rfc defines `deflate`
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl Encoding {
+    /// the name in `Accept-Encoding` and `Content-Encoding`
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// the supported encodings, the most preferred first
+#[cfg(feature = "brotli")]
+const ENCODINGS: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+#[cfg(not(feature = "brotli"))]
+const ENCODINGS: [Encoding; 2] = [Encoding::Gzip, Encoding::Deflate];
+
+/// pick the encoding of the response from the `Accept-Encoding` values
+///
+/// the one with the highest quality value wins, ties are broken by the
+/// server preference. `*` stands for the encodings not listed and `q=0`
+/// means not acceptable
+pub(crate) fn negotiate<'a>(values: impl Iterator<Item = &'a [u8]>) -> Option<Encoding> {
+    let mut listed = [None; ENCODINGS.len()];
+    let mut any = None;
+    for coding in values.flat_map(|v| v.split(|&b| b == b',')) {
+        let mut params = coding.split(|&b| b == b';');
+        let name = trim(params.next().unwrap_or_default());
+        let q = params
+            .find_map(|p| {
+                let p = trim(p);
+                p.strip_prefix(b"q=").or_else(|| p.strip_prefix(b"Q="))
+            })
+            .map_or(1.0, parse_q);
+        if name == b"*" {
+            any = Some(q);
+        } else if let Some(i) = ENCODINGS
+            .iter()
+            .position(|e| name.eq_ignore_ascii_case(e.name().as_bytes()))
+        {
+            listed[i] = Some(q);
+        }
+    }
+
+    let mut best = None;
+    let mut best_q = 0.0;
+    for (encoding, q) in ENCODINGS.iter().zip(listed) {
+        let q = q.or(any).unwrap_or(0.0);
+        if q > best_q {
+            best = Some(*encoding);
+            best_q = q;
+        }
+    }
+    best
+}
+
+/// whether a `Content-Type` is already compressed so gzip wouldn't help
+pub(crate) fn is_compressed_type(content_type: &str) -> bool {
+    let ty = content_type.trim().to_ascii_lowercase();
+    if ty.starts_with("image/svg") {
+        return false;
+    }
+    ["image/", "audio/", "video/", "font/woff"]
+        .iter()
+        .any(|p| ty.starts_with(p))
+        || [
+            "application/gzip",
+            "application/x-gzip",
+            "application/zip",
+            "application/x-bzip2",
+            "application/x-xz",
+            "application/x-7z-compressed",
+            "application/x-rar-compressed",
+            "application/zstd",
+        ]
+        .iter()
+        .any(|p| ty.starts_with(p))
+}
+
+/// compress the whole body
+pub(crate) fn compress(encoding: Encoding, data: &[u8]) -> Vec<u8> {
+    let out = Vec::with_capacity(data.len() / 2);
+    // writing to a `Vec` never fails
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(out, Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        Encoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(out, Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli => {
+            // a middle quality, the best one is too slow for dynamic responses
+            let mut encoder = brotli::CompressorWriter::new(out, 4096, 5, 22);
+            encoder.write_all(data).unwrap();
+            encoder.into_inner()
+        }
+    }
+}
+
+/// an encoder compressing what's written to it into the inner writer
+///
+/// `Identity` passes the data through, for a client that accepts no encoding
+pub(crate) enum StreamEncoder<W: Write> {
+    Identity(W),
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<KeepError<W>>>),
+}
+
+/// the inner writer of the brotli encoder, it keeps the write error
+///
+/// `CompressorWriter::into_inner` writes the end of the stream and drops the
+/// error, it's returned from `StreamEncoder::finish` instead
+#[cfg(feature = "brotli")]
+pub(crate) struct KeepError<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "brotli")]
+impl<W: Write> Write for KeepError<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.inner.write(data).map_err(|e| self.keep(e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(|e| self.keep(e))
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl<W> KeepError<W> {
+    /// keep the first error and pass a copy of it on to the encoder
+    fn keep(&mut self, e: io::Error) -> io::Error {
+        let copy = io::Error::new(e.kind(), e.to_string());
+        self.error.get_or_insert(e);
+        copy
+    }
+
+    fn into_result(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.inner),
+        }
+    }
+}
+
+impl<W: Write> StreamEncoder<W> {
+    pub(crate) fn new(encoding: Option<Encoding>, w: W) -> Self {
+        match encoding {
+            None => StreamEncoder::Identity(w),
+            Some(Encoding::Gzip) => StreamEncoder::Gzip(GzEncoder::new(w, Compression::default())),
+            Some(Encoding::Deflate) => {
+                StreamEncoder::Deflate(ZlibEncoder::new(w, Compression::default()))
+            }
+            #[cfg(feature = "brotli")]
+            Some(Encoding::Brotli) => {
+                let w = KeepError {
+                    inner: w,
+                    error: None,
+                };
+                StreamEncoder::Brotli(Box::new(brotli::CompressorWriter::new(w, 4096, 5, 22)))
+            }
+        }
+    }
+
+    /// write the end of the compressed stream and return the inner writer
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            StreamEncoder::Identity(w) => Ok(w),
+            StreamEncoder::Gzip(e) => e.finish(),
+            StreamEncoder::Deflate(e) => e.finish(),
+            #[cfg(feature = "brotli")]
+            StreamEncoder::Brotli(e) => e.into_inner().into_result(),
+        }
+    }
+}
+
+impl<W: Write> Write for StreamEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            StreamEncoder::Identity(w) => w.write(data),
+            StreamEncoder::Gzip(e) => e.write(data),
+            StreamEncoder::Deflate(e) => e.write(data),
+            #[cfg(feature = "brotli")]
+            StreamEncoder::Brotli(e) => e.write(data),
+        }
+    }
+
+    /// the compressed data so far is written out and the inner writer flushed
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            StreamEncoder::Identity(w) => w.flush(),
+            StreamEncoder::Gzip(e) => e.flush(),
+            StreamEncoder::Deflate(e) => e.flush(),
+            #[cfg(feature = "brotli")]
+            StreamEncoder::Brotli(e) => e.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::Router;
+    use crate::test;
+
+    fn negotiate_one(value: &str) -> Option<Encoding> {
+        negotiate(std::iter::once(value.as_bytes()))
+    }
+
+    #[test]
+    fn best_quality_wins() {
+        assert_eq!(negotiate_one("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_one("deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            negotiate_one("gzip;q=0.5, deflate"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate_one("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_one("GZIP; Q=1"), Some(Encoding::Gzip));
+        let values = [&b"deflate;q=0.2"[..], b"gzip;q=0.1"];
+        assert_eq!(negotiate(values.iter().copied()), Some(Encoding::Deflate));
+    }
+
+    #[test]
+    fn not_acceptable_encodings() {
+        for value in ["", "identity", "gzip;q=0", "gzip;q=2", "gzip;q=x", "*;q=0"] {
+            assert_eq!(negotiate_one(value), None, "{}", value);
+        }
+        // the others are still acceptable
+        let encoding = negotiate_one("*, gzip;q=0");
+        assert!(encoding.is_some_and(|e| e != Encoding::Gzip));
+    }
+
+    #[test]
+    fn compressed_types() {
+        assert!(is_compressed_type("image/png"));
+        assert!(is_compressed_type(" Application/Zip"));
+        assert!(!is_compressed_type("image/svg+xml"));
+        assert!(!is_compressed_type("text/html; charset=utf-8"));
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .get("/text", |_req, rsp| {
+                rsp.auto_compress(true);
+                rsp.body_vec(b"hello ".repeat(1000));
+                Ok(())
+            })
+            .get("/png", |_req, rsp| {
+                rsp.auto_compress(true);
+                rsp.add_header("Content-Type", "image/png");
+                rsp.body_vec(b"hello ".repeat(1000));
+                Ok(())
+            })
+            .get("/small", |_req, rsp| {
+                rsp.auto_compress(true);
+                rsp.body("hello");
+                Ok(())
+            })
+    }
+
+    fn head(path: &str, accept: Option<&str>) -> String {
+        let mut req = format!("GET {} HTTP/1.1\r\nHost: a\r\n", path);
+        if let Some(accept) = accept {
+            req.push_str(&format!("Accept-Encoding: {}\r\n", accept));
+        }
+        req.push_str("\r\n");
+        let rsp = test::serve(app(), req.as_bytes());
+        let rsp = String::from_utf8_lossy(&rsp);
+        rsp.split("\r\n\r\n").next().unwrap().to_owned()
+    }
+
+    #[test]
+    fn compressed_body_varies() {
+        for (accept, encoding) in [("gzip", "gzip"), ("deflate", "deflate")] {
+            let rsp = head("/text", Some(accept));
+            assert!(rsp.contains(&format!("\r\nContent-Encoding: {}", encoding)));
+            assert!(rsp.contains("\r\nVary: Accept-Encoding"));
+        }
+    }
+
+    #[test]
+    fn uncompressed_body_varies_when_negotiated() {
+        for accept in [None, Some("identity"), Some("gzip;q=0")] {
+            let rsp = head("/text", accept);
+            assert!(!rsp.contains("Content-Encoding"), "{:?}", accept);
+            assert!(rsp.contains("\r\nVary: Accept-Encoding"), "{:?}", accept);
+        }
+    }
+
+    #[test]
+    fn not_compressible_body_does_not_vary() {
+        for path in ["/png", "/small"] {
+            let rsp = head(path, Some("gzip"));
+            assert!(!rsp.contains("Content-Encoding"), "{}", path);
+            assert!(!rsp.contains("Vary"), "{}", path);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "brotli"))]
+mod brotli_tests {
+    use super::*;
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _data: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn brotli_finish_returns_the_write_error() {
+        let mut encoder = StreamEncoder::new(Some(Encoding::Brotli), Broken);
+        // it's buffered by the encoder, only the end of the stream is written
+        encoder.write_all(b"hello").unwrap();
+        let err = encoder.finish().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn brotli_finish_returns_the_writer() {
+        let mut encoder = StreamEncoder::new(Some(Encoding::Brotli), Vec::new());
+        encoder.write_all(b"hello").unwrap();
+        assert!(!encoder.finish().unwrap().is_empty());
+    }
+}
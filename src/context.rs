@@ -0,0 +1,50 @@
+//! the state kept for a connection across its requests
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// the values stored for the lifetime of a connection, one for each type
+///
+/// it's created when the connection is accepted and reached by
+/// `Request::context` in every request served on it, like the user
+/// authenticated by a previous request
+#[derive(Default)]
+pub struct ConnContext {
+    values: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl ConnContext {
+    /// store a value, returning the previous one of the same type
+    pub fn insert<T: Send + 'static>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Send + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Send + 'static>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// get the value of the type, inserting the default one first if there's none
+    pub fn get_or_default<T: Default + Send + 'static>(&mut self) -> &mut T {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::<T>::default())
+            .downcast_mut()
+            .unwrap()
+    }
+
+    pub fn remove<T: Send + 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.downcast().ok().map(|v| *v))
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
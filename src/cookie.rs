@@ -0,0 +1,287 @@
+//! parsing the `Cookie` header and building `Set-Cookie`
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// the cookies sent with a request
+///
+/// it's returned by `Request::cookies`, the pairs are kept in the order
+/// they are sent and the values are not decoded
+pub struct Cookies<'req> {
+    pairs: Vec<(&'req str, &'req str)>,
+}
+
+impl<'req> Cookies<'req> {
+    pub(crate) fn parse(values: impl Iterator<Item = &'req [u8]>) -> Self {
+        let pairs = values
+            .filter_map(|v| std::str::from_utf8(v).ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (!name.is_empty()).then_some((name, value))
+            })
+            .collect();
+        Cookies { pairs }
+    }
+
+    /// the value of the first cookie with the given name, compared case sensitively
+    pub fn get(&self, name: &str) -> Option<&'req str> {
+        self.pairs.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'req str, &'req str)> + '_ {
+        self.pairs.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// the `SameSite` attribute of a cookie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// browsers only accept it on a `Secure` cookie
+    None,
+}
+
+/// a cookie to send with `Response::set_cookie`
+///
+/// ```
+/// use may_minihttp::{CookieBuilder, SameSite};
+///
+/// let cookie = CookieBuilder::new("session", "abc123")
+///     .path("/")
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!(cookie.to_string(), "session=abc123; Path=/; HttpOnly; SameSite=Lax");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CookieBuilder {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl CookieBuilder {
+    /// create a cookie with the name and value
+    ///
+    /// panic if the name is not a token or the value has characters not
+    /// allowed in a cookie, like a space, `"`, `,`, `;` or `\`
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        let value = value.into();
+        assert!(is_valid_name(&name), "invalid cookie name: {:?}", name);
+        assert!(is_valid_value(&value), "invalid cookie value: {:?}", value);
+        CookieBuilder {
+            name,
+            value,
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// panic if the path has a `;` or a control character
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        assert!(is_valid_attr(&path), "invalid cookie path: {:?}", path);
+        self.path = Some(path);
+        self
+    }
+
+    /// panic if the domain has a `;` or a control character
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        let domain = domain.into();
+        assert!(
+            is_valid_attr(&domain),
+            "invalid cookie domain: {:?}",
+            domain
+        );
+        self.domain = Some(domain);
+        self
+    }
+
+    /// how long the cookie is kept, in whole seconds, a zero deletes the cookie
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// the `Set-Cookie` value
+impl fmt::Display for CookieBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict")?,
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax")?,
+            Some(SameSite::None) => f.write_str("; SameSite=None")?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// the cookie-octet of rfc 6265, optionally in double quotes
+fn is_valid_value(value: &str) -> bool {
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    value
+        .bytes()
+        .all(|b| matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e))
+}
+
+fn is_valid_attr(value: &str) -> bool {
+    value.bytes().all(|b| b >= 0x20 && b != 0x7f && b != b';')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test;
+    use crate::{HttpService, Request, Response};
+    use std::io;
+
+    #[test]
+    fn cookies_are_parsed() {
+        let headers = [&b"a=1; b=\"two\";c=; =skipped"[..], b"bad; d = 4 "];
+        let cookies = Cookies::parse(headers.iter().copied());
+        assert_eq!(
+            cookies.iter().collect::<Vec<_>>(),
+            [("a", "1"), ("b", "two"), ("c", ""), ("d", "4")]
+        );
+        assert_eq!(cookies.get("d"), Some("4"));
+        assert_eq!(cookies.get("A"), None);
+    }
+
+    #[test]
+    fn all_the_attributes_are_written() {
+        let cookie = CookieBuilder::new("id", "\"x\"")
+            .path("/app")
+            .domain("example.com")
+            .max_age(Duration::from_millis(90_500))
+            .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::None);
+        assert_eq!(
+            cookie.to_string(),
+            "id=\"x\"; Path=/app; Domain=example.com; Max-Age=90; \
+             Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=None"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid cookie value")]
+    fn value_with_a_semicolon_panics() {
+        CookieBuilder::new("a", "b; Path=/");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid cookie name")]
+    fn empty_name_panics() {
+        CookieBuilder::new("", "b");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid cookie path")]
+    fn path_with_a_semicolon_panics() {
+        CookieBuilder::new("a", "b").path("/; Secure");
+    }
+
+    struct Counter;
+
+    impl HttpService for Counter {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            let n: u32 = req
+                .cookies()
+                .get("n")
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(0);
+            rsp.set_cookie(CookieBuilder::new("n", (n + 1).to_string()));
+            rsp.set_cookie(CookieBuilder::new("seen", "1").http_only(true));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn each_cookie_is_its_own_header() {
+        let rsp = test::serve(
+            Counter,
+            b"GET / HTTP/1.1\r\nHost: a\r\nCookie: n=41\r\n\r\n",
+        );
+        let rsp = String::from_utf8(rsp).unwrap();
+        assert!(rsp.contains("\r\nSet-Cookie: n=42\r\n"));
+        assert!(rsp.contains("\r\nSet-Cookie: seen=1; HttpOnly\r\n"));
+    }
+}
@@ -0,0 +1,264 @@
+//! cross origin resource sharing
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::http_server::HttpService;
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::Response;
+
+#[derive(Clone)]
+struct CorsConfig {
+    /// `None` allows any origin
+    origins: Option<Vec<String>>,
+    methods: String,
+    headers: String,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+/// a service that adds the CORS headers to the responses of the inner one
+///
+/// a preflight `OPTIONS` request is answered with `204 No Content` and the
+/// `Access-Control-Allow-*` headers without calling the inner service, the
+/// other requests are passed on and get `Access-Control-Allow-Origin` when
+/// the origin is allowed. by default any origin may send GET, HEAD and POST
+///
+/// ```no_run
+/// use may_minihttp::{Cors, HttpServer, Method, Router};
+///
+/// let router = Router::new().get("/", |_, rsp| {
+///     rsp.text("hello");
+///     Ok(())
+/// });
+/// let cors = Cors::new(router)
+///     .allow_origin("https://example.com")
+///     .allow_methods(&[Method::Get, Method::Put])
+///     .allow_headers(&["Content-Type"]);
+/// HttpServer(cors).start("127.0.0.1:8080").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Cors<S> {
+    inner: S,
+    config: Arc<CorsConfig>,
+    /// the `Access-Control-Allow-Origin` of the current request, for `on_error`
+    allow_origin: Option<String>,
+}
+
+impl<S> Cors<S> {
+    pub fn new(inner: S) -> Self {
+        Cors {
+            inner,
+            config: Arc::new(CorsConfig {
+                origins: None,
+                methods: "GET, HEAD, POST".to_owned(),
+                headers: String::new(),
+                credentials: false,
+                max_age: None,
+            }),
+            allow_origin: None,
+        }
+    }
+
+    /// allow the origin, like `https://example.com`
+    ///
+    /// once an origin is added only the added ones are allowed
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        let config = Arc::make_mut(&mut self.config);
+        config
+            .origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.to_owned());
+        self
+    }
+
+    /// allow any origin, this is the default
+    pub fn allow_any_origin(mut self) -> Self {
+        Arc::make_mut(&mut self.config).origins = None;
+        self
+    }
+
+    /// the methods a preflight allows
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        let methods: Vec<_> = methods.iter().map(|m| m.as_str()).collect();
+        Arc::make_mut(&mut self.config).methods = methods.join(", ");
+        self
+    }
+
+    /// the request headers a preflight allows, none by default
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        Arc::make_mut(&mut self.config).headers = headers.join(", ");
+        self
+    }
+
+    /// allow the requests with cookies or `Authorization`
+    ///
+    /// the origin is then sent back instead of `*` when any origin is allowed
+    pub fn allow_credentials(mut self, credentials: bool) -> Self {
+        Arc::make_mut(&mut self.config).credentials = credentials;
+        self
+    }
+
+    /// how long a preflight result may be cached by the browser
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        Arc::make_mut(&mut self.config).max_age = Some(max_age);
+        self
+    }
+
+    /// the `Access-Control-Allow-Origin` value for the origin of the request
+    fn allowed_origin(&self, origin: &[u8]) -> Option<String> {
+        let origin = std::str::from_utf8(origin).ok()?;
+        match &self.config.origins {
+            None if !self.config.credentials => Some("*".to_owned()),
+            None => Some(origin.to_owned()),
+            Some(origins) => origins
+                .iter()
+                .any(|o| o.eq_ignore_ascii_case(origin))
+                .then(|| origin.to_owned()),
+        }
+    }
+
+    /// the headers depend on the origin unless `*` is sent for all of them
+    fn varies_by_origin(&self) -> bool {
+        self.config.origins.is_some() || self.config.credentials
+    }
+
+    fn add_origin_headers(&self, rsp: &mut Response) {
+        // also when the origin is not allowed, so a cache doesn't serve the
+        // response without the headers to the allowed ones
+        if self.varies_by_origin() {
+            rsp.add_header("Vary", "Origin");
+        }
+        if let Some(allow_origin) = &self.allow_origin {
+            rsp.add_header("Access-Control-Allow-Origin", allow_origin);
+            if self.config.credentials {
+                rsp.add_header("Access-Control-Allow-Credentials", "true");
+            }
+        }
+    }
+}
+
+impl<S: HttpService> HttpService for Cors<S> {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+        self.allow_origin = req.header("origin").and_then(|o| self.allowed_origin(o));
+
+        let preflight = req.method() == Method::Options
+            && req.header("access-control-request-method").is_some();
+        self.add_origin_headers(rsp);
+        if !preflight {
+            return self.inner.call(req, rsp);
+        }
+
+        rsp.status_code("204", "No Content");
+        if self.allow_origin.is_some() {
+            rsp.add_header("Access-Control-Allow-Methods", &self.config.methods);
+            if !self.config.headers.is_empty() {
+                rsp.add_header("Access-Control-Allow-Headers", &self.config.headers);
+            }
+            if let Some(max_age) = self.config.max_age {
+                rsp.add_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn on_error(&mut self, e: &io::Error, rsp: &mut Response) {
+        self.inner.on_error(e, rsp);
+        self.add_origin_headers(rsp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::Router;
+    use crate::test;
+
+    fn hello() -> Router {
+        Router::new().get("/", |_req, rsp| {
+            rsp.body("hello");
+            Ok(())
+        })
+    }
+
+    fn head(rsp: &[u8]) -> String {
+        let rsp = String::from_utf8_lossy(rsp);
+        rsp.split("\r\n\r\n").next().unwrap().to_owned()
+    }
+
+    fn get(origin: Option<&str>) -> Vec<u8> {
+        let mut req = "GET / HTTP/1.1\r\nHost: a\r\n".to_owned();
+        if let Some(origin) = origin {
+            req.push_str(&format!("Origin: {}\r\n", origin));
+        }
+        req.push_str("\r\n");
+        req.into_bytes()
+    }
+
+    #[test]
+    fn allow_list_varies_for_every_origin() {
+        let cors = Cors::new(hello()).allow_origin("https://a.example");
+
+        let rsp = head(&test::serve(cors.clone(), &get(Some("https://a.example"))));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Origin: https://a.example"));
+        assert!(rsp.contains("\r\nVary: Origin"));
+
+        let rsp = head(&test::serve(cors.clone(), &get(Some("https://b.example"))));
+        assert!(!rsp.contains("Access-Control-Allow-Origin"));
+        assert!(rsp.contains("\r\nVary: Origin"));
+
+        let rsp = head(&test::serve(cors, &get(None)));
+        assert!(!rsp.contains("Access-Control-Allow-Origin"));
+        assert!(rsp.contains("\r\nVary: Origin"));
+    }
+
+    #[test]
+    fn preflight_is_answered() {
+        let cors = Cors::new(hello())
+            .allow_origin("https://a.example")
+            .allow_methods(&[Method::Get, Method::Put])
+            .allow_headers(&["Content-Type", "X-Id"])
+            .allow_credentials(true)
+            .max_age(Duration::from_secs(600));
+        let req = b"OPTIONS / HTTP/1.1\r\nHost: a\r\nOrigin: https://a.example\r\n\
+            Access-Control-Request-Method: PUT\r\n\r\n";
+        let rsp = head(&test::serve(cors, req));
+        assert!(rsp.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Origin: https://a.example"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Credentials: true"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Methods: GET, PUT"));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Headers: Content-Type, X-Id"));
+        assert!(rsp.contains("\r\nAccess-Control-Max-Age: 600"));
+    }
+
+    #[test]
+    fn allow_list_varies_for_a_rejected_preflight() {
+        let cors = Cors::new(hello()).allow_origin("https://a.example");
+        let req = b"OPTIONS / HTTP/1.1\r\nHost: a\r\nOrigin: https://b.example\r\n\
+            Access-Control-Request-Method: GET\r\n\r\n";
+        let rsp = head(&test::serve(cors, req));
+        assert!(rsp.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!rsp.contains("Access-Control-Allow-Methods"));
+        assert!(rsp.contains("\r\nVary: Origin"));
+    }
+
+    #[test]
+    fn any_origin_does_not_vary() {
+        let rsp = head(&test::serve(
+            Cors::new(hello()),
+            &get(Some("https://a.example")),
+        ));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Origin: *"));
+        assert!(!rsp.contains("Vary"));
+    }
+
+    #[test]
+    fn credentials_send_the_origin_back() {
+        let cors = Cors::new(hello()).allow_credentials(true);
+        let rsp = head(&test::serve(cors, &get(Some("https://a.example"))));
+        assert!(rsp.contains("\r\nAccess-Control-Allow-Origin: https://a.example"));
+        assert!(rsp.contains("\r\nVary: Origin"));
+    }
+}
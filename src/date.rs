@@ -1,5 +1,5 @@
-use std::cell::UnsafeCell;
 use std::fmt::{self, Write};
+use std::sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use bytes::BytesMut;
@@ -7,44 +7,95 @@ use lazy_static::lazy_static;
 
 // "Sun, 06 Nov 1994 08:49:37 GMT".len()
 const DATE_VALUE_LENGTH: usize = 29;
+// the date in 4 byte words, 8 of them hold the 29 bytes
+const DATE_WORDS: usize = 8;
 
 lazy_static! {
     static ref CURRENT_DATE: Arc<DataWrap> = {
-        let date = Arc::new(DataWrap(UnsafeCell::new(Date::new())));
+        let date = Arc::new(DataWrap::new());
         let date_clone = date.clone();
         may::go!(move || loop {
             may::coroutine::sleep(std::time::Duration::from_millis(500));
-            unsafe { &mut *(date_clone.0).get() }.update();
+            date_clone.update();
         });
         date
     };
 }
 
-struct DataWrap(UnsafeCell<Date>);
-unsafe impl Sync for DataWrap {}
+/// the formatted date is refreshed by a single coroutine, so the clock is not read per request
+///
+/// it's a seqlock, the sequence is odd while the date is written. the words
+/// of the date are atomics so a read racing the update is not a data race,
+/// and a reader that saw the sequence change reads again instead of using a
+/// torn date
+struct DataWrap {
+    seq: AtomicUsize,
+    words: [AtomicU32; DATE_WORDS],
+}
+
+impl DataWrap {
+    fn new() -> Self {
+        let date = DataWrap {
+            seq: AtomicUsize::new(0),
+            words: Default::default(),
+        };
+        date.update();
+        date
+    }
+
+    fn update(&self) {
+        let mut date = Date::new();
+        date.update();
+        self.store(&date.bytes);
+    }
+
+    /// only one thread may store
+    fn store(&self, bytes: &[u8; DATE_WORDS * 4]) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (word, b) in self.words.iter().zip(bytes.chunks_exact(4)) {
+            word.store(
+                u32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+                Ordering::Relaxed,
+            );
+        }
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    fn load(&self) -> [u8; DATE_WORDS * 4] {
+        let mut bytes = [0; DATE_WORDS * 4];
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                for (word, b) in self.words.iter().zip(bytes.chunks_exact_mut(4)) {
+                    b.copy_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes());
+                }
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return bytes;
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+}
 
 #[doc(hidden)]
 pub fn set_date(dst: &mut BytesMut) {
-    let date = unsafe { &*CURRENT_DATE.0.get() };
-    dst.extend_from_slice(date.as_bytes());
+    let date = CURRENT_DATE.load();
+    dst.extend_from_slice(&date[..DATE_VALUE_LENGTH]);
 }
 
 struct Date {
-    bytes: [u8; DATE_VALUE_LENGTH],
+    bytes: [u8; DATE_WORDS * 4],
 }
 
 impl Date {
     fn new() -> Date {
-        let mut date = Date {
-            bytes: [0; DATE_VALUE_LENGTH],
-        };
-        date.update();
-        date
-    }
-
-    #[inline]
-    fn as_bytes(&self) -> &[u8] {
-        &self.bytes
+        Date {
+            bytes: [0; DATE_WORDS * 4],
+        }
     }
 
     fn update(&mut self) {
@@ -56,7 +107,56 @@ impl Date {
 
 impl fmt::Write for Date {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.bytes.copy_from_slice(s.as_bytes());
+        self.bytes[..DATE_VALUE_LENGTH].copy_from_slice(s.as_bytes());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> [u8; DATE_WORDS * 4] {
+        let mut bytes = [0; DATE_WORDS * 4];
+        bytes[..DATE_VALUE_LENGTH].copy_from_slice(s.as_bytes());
+        bytes
+    }
+
+    // it's also run by `cargo miri test date`, which checks for the data races
+    #[test]
+    fn load_never_sees_a_torn_date() {
+        let a = date("Sun, 06 Nov 1994 08:49:37 GMT");
+        let b = date("Mon, 17 Dec 2001 19:58:26 GMT");
+        let wrap = Arc::new(DataWrap::new());
+        wrap.store(&a);
+        let rounds = if cfg!(miri) { 100 } else { 100_000 };
+
+        let writer = {
+            let wrap = wrap.clone();
+            std::thread::spawn(move || {
+                for i in 0..rounds {
+                    wrap.store(if i % 2 == 0 { &b } else { &a });
+                }
+            })
+        };
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let wrap = wrap.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..rounds {
+                        let read = wrap.load();
+                        assert!(
+                            read == a || read == b,
+                            "{:?}",
+                            String::from_utf8_lossy(&read)
+                        );
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! entity tags for conditional requests
+
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+/// a weak etag from the length and the modification time of a file
+pub fn file_etag(meta: &Metadata) -> String {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        meta.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+}
+
+/// a weak etag from a hash of the content
+///
+/// the hash is stable across runs, so servers behind the same address agree on it
+pub fn content_etag(data: &[u8]) -> String {
+    // 64 bit FNV-1a
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("W/\"{:x}-{:x}\"", data.len(), hash)
+}
+
+/// whether an `If-None-Match` value matches the etag, so the client has the content
+///
+/// the weak comparison is used like the rfc requires for `If-None-Match`
+pub(crate) fn none_match(if_none_match: &str, etag: &str) -> bool {
+    let etag = opaque(etag);
+    if_none_match.trim() == "*" || if_none_match.split(',').any(|tag| opaque(tag) == etag)
+}
+
+/// the etag without the weak prefix
+fn opaque(tag: &str) -> &str {
+    let tag = tag.trim();
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test;
+    use crate::{HttpService, Request, Response};
+    use std::io;
+
+    #[test]
+    fn none_match_is_a_weak_comparison() {
+        let etag = "W/\"4-2a\"";
+        assert!(none_match("\"4-2a\"", etag));
+        assert!(none_match("\"x\", W/\"4-2a\"", etag));
+        assert!(none_match(" * ", etag));
+        assert!(!none_match("\"4-2b\"", etag));
+        assert!(!none_match("", etag));
+    }
+
+    #[test]
+    fn content_etag_is_stable() {
+        assert_eq!(content_etag(b""), "W/\"0-cbf29ce484222325\"");
+        assert_eq!(content_etag(b"a"), "W/\"1-af63dc4c8601ec8c\"");
+        assert_ne!(content_etag(b"ab"), content_etag(b"ba"));
+    }
+
+    struct Hello;
+
+    impl HttpService for Hello {
+        fn call(&mut self, _req: Request, rsp: &mut Response) -> io::Result<()> {
+            rsp.auto_etag().body("a");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn auto_etag_answers_304() {
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n\
+            GET / HTTP/1.1\r\nHost: a\r\nIf-None-Match: \"1-af63dc4c8601ec8c\"\r\n\r\n\
+            POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\n\
+            If-None-Match: \"1-af63dc4c8601ec8c\"\r\n\r\n";
+        let rsp = String::from_utf8(test::serve(Hello, req)).unwrap();
+        let rsps: Vec<_> = rsp.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(rsps.len(), 3);
+        assert!(rsps[0].starts_with("200 Ok\r\n"));
+        assert!(rsps[0].contains("\r\nETag: W/\"1-af63dc4c8601ec8c\"\r\n"));
+        assert!(rsps[0].ends_with("\r\n\r\na"));
+        assert!(rsps[1].starts_with("304 Not Modified\r\n"));
+        assert!(rsps[1].ends_with("\r\n\r\n"));
+        // only a GET or HEAD is conditional
+        assert!(rsps[2].starts_with("200 Ok\r\n"));
+    }
+}
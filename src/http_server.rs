@@ -1,55 +1,80 @@
 //! http server implementation on top of `MAY`
 
-use std::io::{self, Read, Write};
-use std::mem::MaybeUninit;
-use std::net::ToSocketAddrs;
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+#[cfg(not(unix))]
+use std::net::Ipv6Addr;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::request::{self, Request};
+use crate::context::ConnContext;
+use crate::method::Method;
+use crate::metrics::Metrics;
+use crate::pool::{BufferPool, Buffers, PooledBuffers};
+use crate::request::{self, BodyState, ParseOptions, Rejection, Request};
 use crate::response::{self, Response};
+use crate::shutdown::{self, Shutdown};
+use crate::transport::{
+    flush_wait, is_disconnect, read_wait, write_wait, Conn, Listener, Transport,
+};
+use crate::upgrade::Upgraded;
 use bytes::Buf;
 use bytes::{BufMut, BytesMut};
-#[cfg(unix)]
-use may::io::WaitIo;
 use may::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use may::os::unix::net::UnixListener;
+use may::sync::Semphore;
 use may::{coroutine, go};
-use memchr::memmem::FinderRev;
+use memchr::memmem::Finder;
 
 macro_rules! t {
-    ($e: expr) => {
+    ($config: expr, $e: expr) => {
         match $e {
             Ok(val) => val,
             Err(err) => {
-                if err.kind() == io::ErrorKind::ConnectionReset
-                    || err.kind() == io::ErrorKind::UnexpectedEof
-                {
-                    // info!("http server read req: connection closed");
-                    return;
-                }
-
-                error!("call = {:?}\nerr = {:?}", stringify!($e), err);
+                io_error(&$config, &err, stringify!($e));
                 return;
             }
         }
     };
 }
 
-macro_rules! t_c {
-    ($e: expr) => {
-        match $e {
-            Ok(val) => val,
-            Err(err) => {
-                error!("call = {:?}\nerr = {:?}", stringify!($e), err);
-                continue;
-            }
-        }
-    };
+/// report an io error that ends a connection or an accept, to the hook if there's one
+///
+/// without a hook only the real errors are logged, not the clients going away
+fn io_error(config: &Config, err: &io::Error, call: &str) {
+    if let Some(on_io_error) = &config.on_io_error {
+        return on_io_error(err);
+    }
+    if !is_disconnect(err) {
+        error!("call = {:?}\nerr = {:?}", call, err);
+    }
 }
 
 /// the http service trait
 /// user code should supply a type that impl the `call` method for the http server
 ///
+/// each connection is served by its own service value, the state that lives
+/// as long as the connection can also be kept in `Request::context`
 pub trait HttpService {
     fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()>;
+
+    /// render the response for an error returned by `call`
+    ///
+    /// the response starts out empty, the default is a
+    /// `500 Internal Server Error` with the error message as the body
+    fn on_error(&mut self, e: &io::Error, rsp: &mut Response) {
+        rsp.status_code("500", "Internal Server Error");
+        rsp.body_mut().extend_from_slice(e.to_string().as_bytes());
+    }
 }
 
 pub trait HttpServiceFactory: Send + Sized + 'static {
@@ -61,140 +86,1308 @@ pub trait HttpServiceFactory: Send + Sized + 'static {
     /// return a coroutine that you can cancel it when need to stop the service
     fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
         let listener = TcpListener::bind(addr)?;
+        let config = Arc::new(Config::default());
+        let accept = tcp_accept(&config);
         go!(
             coroutine::Builder::new().name("TcpServerFac".to_owned()),
-            move || {
-                for stream in listener.incoming() {
-                    let stream = t_c!(stream);
-                    let service = self.new_service();
-                    go!(move || each_connection_loop(stream, service));
+            move || serve(listener.incoming(), config, || self.new_service(), accept)
+        )
+    }
+}
+
+/// accept the connections and spawn a coroutine for each of them
+///
+/// `accept` turns the accepted stream into the transport in the connection coroutine
+fn serve<T, C, S, F, A>(
+    incoming: impl Iterator<Item = io::Result<C>>,
+    config: Arc<Config>,
+    mut new_service: F,
+    accept: A,
+) where
+    T: HttpService + Send + 'static,
+    C: Transport + Send + 'static,
+    S: Transport + 'static,
+    F: FnMut() -> T,
+    A: Fn(C) -> io::Result<S> + Clone + Send + 'static,
+{
+    // numbers the connections in the coroutine names
+    let mut accepted: u64 = 0;
+    // the wait after a failed accept, doubled while they keep failing
+    let mut backoff = None;
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => {
+                backoff = None;
+                stream
+            }
+            Err(err) => {
+                io_error(&config, &err, "accept");
+                // like running out of file descriptors, retrying right away would spin
+                let delay = backoff.map_or(MIN_ACCEPT_BACKOFF, |d: Duration| d * 2);
+                let delay = delay.min(config.max_accept_backoff);
+                backoff = Some(delay);
+                coroutine::sleep(delay);
+                continue;
+            }
+        };
+        if config.is_stopping() {
+            break;
+        }
+        let slot = match config.max_connections.as_ref() {
+            Some(limit) => {
+                match limit.overload {
+                    // the next connections wait in the listen backlog
+                    Overload::Wait => limit.slots.wait(),
+                    Overload::Reject => {
+                        if !limit.slots.try_wait() {
+                            let config = config.clone();
+                            let accept = accept.clone();
+                            go!(move || {
+                                let mut stream = t!(config, accept(stream));
+                                let _ = reject_overloaded(&mut stream, &config);
+                            });
+                            continue;
+                        }
+                    }
                 }
+                // taken right away, so the slot is given back whatever happens next
+                Some(Slot(limit.slots.clone()))
             }
+            None => None,
+        };
+        let service = new_service();
+        accepted += 1;
+        let name = match stream.peer_addr() {
+            Ok(addr) => format!("Conn-{} {}", accepted, addr),
+            Err(_) => format!("Conn-{}", accepted),
+        };
+        let mut builder = coroutine::Builder::new().name(name);
+        if let Some(size) = config.stack_size {
+            builder = builder.stack_size(size);
+        }
+        let config = config.clone();
+        let accept = accept.clone();
+        let spawned = go!(builder, move || {
+            let _slot = slot;
+            let mut stream = stream;
+            let source = if config.proxy_protocol {
+                t!(config, read_proxy_header(&mut stream, &config))
+            } else {
+                None
+            };
+            let stream = t!(config, accept(stream));
+            each_connection_loop(stream, service, &config, source)
+        });
+        if let Err(err) = spawned {
+            error!("failed to spawn the connection coroutine: err = {:?}", err);
+        }
+    }
+
+    if let Some(shutdown) = config.shutdown.as_ref() {
+        shutdown.drain();
+    }
+}
+
+/// read the PROXY header before anything else is done on the connection
+///
+/// the read timeout applies so a client can't hold the connection without sending it
+fn read_proxy_header<C: Transport>(
+    stream: &mut C,
+    config: &Config,
+) -> io::Result<Option<SocketAddr>> {
+    stream.set_read_timeout(config.read_timeout)?;
+    let source = crate::proxy::read_header(stream)?;
+    stream.set_read_timeout(None)?;
+    Ok(source)
+}
+
+/// try each resolved address in turn until one works, like `TcpListener::bind`
+#[cfg(unix)]
+fn each_addr<L: ToSocketAddrs, R>(
+    addr: L,
+    mut f: impl FnMut(SocketAddr) -> io::Result<R>,
+) -> io::Result<R> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match f(addr) {
+            Ok(r) => return Ok(r),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
         )
+    }))
+}
+
+/// bind the listener of the server with the configured socket options
+fn bind_listener<L: ToSocketAddrs>(addr: L, config: &Config) -> io::Result<TcpListener> {
+    #[cfg(unix)]
+    if let Some(backlog) = config.backlog {
+        return each_addr(addr, |addr| crate::listener::bind(addr, backlog));
     }
+    #[cfg(not(unix))]
+    let _ = config;
+    TcpListener::bind(addr)
 }
 
-fn internal_error_rsp(e: io::Error, buf: &mut BytesMut) -> Response {
+/// the accept of a plain tcp connection
+fn tcp_accept(config: &Config) -> impl Fn(TcpStream) -> io::Result<TcpStream> + Clone + Send {
+    let nodelay = config.tcp_nodelay;
+    let keepalive = config.socket_keepalive;
+    move |stream| {
+        if nodelay {
+            stream.set_nodelay(true)?;
+        }
+        #[cfg(unix)]
+        if let Some(idle) = keepalive {
+            crate::listener::set_keepalive(&stream, idle)?;
+        }
+        #[cfg(not(unix))]
+        let _ = keepalive;
+        Ok(stream)
+    }
+}
+
+fn internal_error_rsp<'a, T: HttpService>(
+    service: &mut T,
+    e: io::Error,
+    buf: &'a mut BytesMut,
+) -> Response<'a> {
     error!("error in service: err = {:?}", e);
     buf.clear();
     let mut err_rsp = Response::new(buf);
-    err_rsp.status_code("500", "Internal Server Error");
-    err_rsp
-        .body_mut()
-        .extend_from_slice(e.to_string().as_bytes());
+    service.on_error(&e, &mut err_rsp);
     err_rsp
 }
 
+/// turn the payload of a panic in the service into the error answered with 500
+fn panic_error(payload: Box<dyn Any + Send>) -> io::Error {
+    let msg = match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => (*msg).to_owned(),
+            Err(_) => "Box<dyn Any>".to_owned(),
+        },
+    };
+    io::Error::new(io::ErrorKind::Other, format!("service panicked: {}", msg))
+}
+
+/// the response for a request that is rejected before reaching the service
+fn rejection_rsp<'a>(rejection: &Rejection, buf: &'a mut BytesMut) -> Response<'a> {
+    buf.clear();
+    let mut rsp = Response::new(buf);
+    rsp.status_code(rejection.code, rejection.msg);
+    rsp.set_keep_alive(false);
+    rsp
+}
+
+/// answer the `OPTIONS *` and `TRACE` requests the server handles itself,
+/// return false to pass the request to the service
+fn builtin_rsp(config: &Config, req: &Request, rsp: &mut Response) -> bool {
+    match req.method() {
+        Method::Options if req.parameters.path == Some("*") => match &config.options_allow {
+            Some(allow) => {
+                rsp.add_header("Allow", allow);
+                true
+            }
+            None => false,
+        },
+        Method::Trace => match config.trace {
+            Trace::Service => false,
+            Trace::Echo => {
+                rsp.add_header("Content-Type", "message/http");
+                let body = rsp.body_mut();
+                body.extend_from_slice(req.method().as_str().as_bytes());
+                body.extend_from_slice(b" ");
+                body.extend_from_slice(req.parameters.path.unwrap_or("").as_bytes());
+                body.extend_from_slice(b" ");
+                body.extend_from_slice(req.version().as_str().as_bytes());
+                body.extend_from_slice(b"\r\n");
+                // the credentials are not echoed, a script could read them in the response
+                let sensitive = ["authorization", "proxy-authorization", "cookie"];
+                for (name, value) in req.headers() {
+                    if sensitive.iter().any(|s| name.eq_ignore_ascii_case(s)) {
+                        continue;
+                    }
+                    body.extend_from_slice(name.as_bytes());
+                    body.extend_from_slice(b": ");
+                    body.extend_from_slice(value);
+                    body.extend_from_slice(b"\r\n");
+                }
+                true
+            }
+            Trace::Reject => {
+                rsp.status_code("405", "Method Not Allowed");
+                if let Some(allow) = &config.options_allow {
+                    rsp.add_header("Allow", allow);
+                }
+                true
+            }
+        },
+        _ => false,
+    }
+}
+
+/// the status of a response the service didn't call `status_code` on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultStatus {
+    /// `200 Ok`, with an empty body if none is set
+    Ok,
+    /// `204 No Content` when the body is empty too, `200 Ok` otherwise
+    NoContent,
+}
+
+/// what the server does with a `TRACE` request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trace {
+    /// pass it to the service like any other request
+    Service,
+    /// send the request head back as a `message/http` body, without the credentials
+    Echo,
+    /// answer `405 Method Not Allowed`
+    Reject,
+}
+
+/// answer a connection over `max_connections` with a `503` and close it
+fn reject_overloaded<S: Transport>(stream: &mut S, config: &Config) -> io::Result<()> {
+    let rejection = Rejection {
+        code: "503",
+        msg: "Service Unavailable",
+    };
+    let mut body_buf = BytesMut::new();
+    let mut rsp = rejection_rsp(&rejection, &mut body_buf);
+    rsp.add_header("Retry-After", "1");
+    rsp.set_server(config.server_name.as_deref());
+    let mut buf = BytesMut::with_capacity(256);
+    response::encode(rsp, &mut buf);
+    if let Some(metrics) = &config.metrics {
+        metrics.response(rejection.code, buf.len());
+    }
+    // the stream is still blocking, the coroutine is parked while writing
+    stream.write_all(&buf)?;
+    stream.flush()
+}
+
+/// what the server does with a new connection when `max_connections` are open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overload {
+    /// stop accepting until a connection closes
+    Wait,
+    /// answer `503 Service Unavailable` and close the connection
+    Reject,
+}
+
+struct ConnectionLimit {
+    /// a slot for each connection that may be opened
+    slots: Arc<Semphore>,
+    overload: Overload,
+}
+
+/// give the connection slot back when the connection ends, even on a panic
+struct Slot(Arc<Semphore>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.post();
+    }
+}
+
+/// the first wait after a failed accept
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+
+/// the default limit of the request body size, 2 MiB
+pub(crate) const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// the default limit of the request line and headers size, 64 KiB
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// headers that fit in the stack array, more than this are allocated on the heap
+pub(crate) const INLINE_HEADERS: usize = 32;
+
+/// the per server settings shared by all the connections
+pub(crate) struct Config {
+    max_headers: usize,
+    max_body_size: usize,
+    strict_headers: bool,
+    max_header_bytes: usize,
+    req_buf_size: usize,
+    rsp_buf_size: usize,
+    /// the free space made in the request buffer for a read, `req_buf_size` if not set
+    read_chunk_size: Option<usize>,
+    /// the most of the queued responses kept before writing them
+    rsp_buf_limit: Option<usize>,
+    buffer_pool: Option<BufferPool>,
+    body_buf_size: usize,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    tcp_nodelay: bool,
+    tcp_cork: bool,
+    socket_keepalive: Option<Duration>,
+    backlog: Option<u32>,
+    max_accept_backoff: Duration,
+    max_requests: Option<usize>,
+    stack_size: Option<usize>,
+    max_connections: Option<ConnectionLimit>,
+    proxy_protocol: bool,
+    /// the `Allow` value of an `OPTIONS *` answered by the server
+    options_allow: Option<String>,
+    trace: Trace,
+    default_status: DefaultStatus,
+    request_id: bool,
+    metrics: Option<Arc<Metrics>>,
+    server_name: Option<String>,
+    access_log: Option<Box<AccessLogFn>>,
+    on_io_error: Option<Box<IoErrorFn>>,
+    shutdown: Option<Arc<shutdown::State>>,
+    #[cfg(feature = "compress")]
+    compress_min_size: usize,
+}
+
+impl Config {
+    fn is_stopping(&self) -> bool {
+        self.shutdown.as_ref().is_some_and(|s| s.is_stopping())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_headers: INLINE_HEADERS,
+            max_body_size: MAX_BODY_SIZE,
+            strict_headers: true,
+            max_header_bytes: MAX_HEADER_BYTES,
+            req_buf_size: 4096 * 8,
+            rsp_buf_size: 4096 * 32,
+            rsp_buf_limit: None,
+            buffer_pool: None,
+            read_chunk_size: None,
+            body_buf_size: 4096 * 8,
+            read_timeout: None,
+            write_timeout: None,
+            idle_timeout: None,
+            tcp_nodelay: true,
+            tcp_cork: false,
+            socket_keepalive: None,
+            backlog: None,
+            max_accept_backoff: Duration::from_secs(1),
+            max_requests: None,
+            stack_size: None,
+            max_connections: None,
+            proxy_protocol: false,
+            options_allow: None,
+            trace: Trace::Service,
+            default_status: DefaultStatus::Ok,
+            request_id: false,
+            metrics: None,
+            server_name: Some("may".to_owned()),
+            access_log: None,
+            on_io_error: None,
+            shutdown: None,
+            #[cfg(feature = "compress")]
+            compress_min_size: crate::compress::DEFAULT_MIN_SIZE,
+        }
+    }
+}
+
+type AccessLogFn = dyn Fn(&AccessLog) + Send + Sync;
+
+type IoErrorFn = dyn Fn(&io::Error) + Send + Sync;
+
+/// the information about a served request passed to the access log callback
+#[derive(Debug)]
+pub struct AccessLog<'a> {
+    pub method: Method<'a>,
+    /// the request target as it's sent
+    pub path: &'a str,
+    /// the response status code
+    pub status: &'static str,
+    /// the size of the whole response, including the head
+    pub bytes: usize,
+    /// the time from decoding the request to writing the response
+    pub elapsed: Duration,
+    pub peer_addr: Option<SocketAddr>,
+    /// the id of the request when `HttpServer::request_id` is on
+    pub request_id: Option<&'a str>,
+}
+
 /// this is the generic type http server
 /// with a type parameter that impl `HttpService` trait
 ///
+/// it's started with the default settings, the settings methods return a
+/// `HttpServerBuilder` that carries the changed settings
 pub struct HttpServer<T>(pub T);
 
+/// a http server with its settings, created by `HttpServer::builder`
+/// or by any of the settings methods of `HttpServer`
+pub struct HttpServerBuilder<T> {
+    pub(crate) service: T,
+    pub(crate) config: Config,
+}
+
+impl<T> HttpServer<T> {
+    /// turn the server into a builder with the default settings
+    pub fn builder(self) -> HttpServerBuilder<T> {
+        HttpServerBuilder {
+            service: self.0,
+            config: Config::default(),
+        }
+    }
+
+    /// see `HttpServerBuilder::access_log`
+    pub fn access_log<F>(self, f: F) -> HttpServerBuilder<T>
+    where
+        F: Fn(&AccessLog) + Send + Sync + 'static,
+    {
+        self.builder().access_log(f)
+    }
+
+    /// see `HttpServerBuilder::on_io_error`
+    pub fn on_io_error<F>(self, f: F) -> HttpServerBuilder<T>
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.builder().on_io_error(f)
+    }
+}
+
+// the settings methods of `HttpServer`, each one starts a builder
+macro_rules! forward_settings {
+    ($($(#[$attr:meta])* $name:ident($($arg:ident: $ty:ty),*);)*) => {
+        impl<T> HttpServer<T> {
+            $(
+                #[doc = concat!("see `HttpServerBuilder::", stringify!($name), "`")]
+                $(#[$attr])*
+                pub fn $name(self, $($arg: $ty),*) -> HttpServerBuilder<T> {
+                    self.builder().$name($($arg),*)
+                }
+            )*
+        }
+    };
+}
+
+forward_settings! {
+    max_headers(max_headers: usize);
+    read_timeout(timeout: Option<Duration>);
+    idle_timeout(timeout: Option<Duration>);
+    max_body_size(size: usize);
+    tcp_nodelay(nodelay: bool);
+    server_name(name: Option<String>);
+    buffer_config(req_initial: usize, rsp_initial: usize, body_initial: usize);
+    max_header_bytes(size: usize);
+    request_id(enabled: bool);
+    backlog(backlog: u32);
+    max_requests_per_connection(max: usize);
+    max_connections(max: usize, overload: Overload);
+    accept_proxy_protocol(on: bool);
+    options_allow(methods: &[Method]);
+    trace(trace: Trace);
+    socket_keepalive(idle: Option<Duration>);
+    write_timeout(timeout: Option<Duration>);
+    stack_size(size: usize);
+    strict_headers(strict: bool);
+    response_buffer_limit(limit: Option<usize>);
+    read_chunk_size(size: usize);
+    buffer_pool(max: usize);
+    default_status(status: DefaultStatus);
+    max_accept_backoff(max: Duration);
+    tcp_cork(cork: bool);
+    #[cfg(feature = "compress")]
+    compress_min_size(size: usize);
+}
+
+impl<T> HttpServerBuilder<T> {
+    /// set the maximum number of headers accepted in a request, default is 32
+    ///
+    /// up to 32 headers are parsed into a stack buffer, only a request with
+    /// more is parsed again with a heap buffer sized for it, so a larger limit
+    /// costs nothing for the common requests. a request with more headers is answered with
+    /// `431 Request Header Fields Too Large` and the connection is closed
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.config.max_headers = max_headers;
+        self
+    }
+
+    /// set the maximum size of the request line and headers, default is 64 KiB
+    ///
+    /// a client that sends more than this without ending the headers is
+    /// answered with `431 Request Header Fields Too Large` and the connection
+    /// is closed
+    pub fn max_header_bytes(mut self, size: usize) -> Self {
+        self.config.max_header_bytes = size;
+        self
+    }
+
+    /// set the maximum size of a request body, default is 2 MiB
+    ///
+    /// a request with a larger `Content-Length` is answered with
+    /// `413 Payload Too Large` and the connection is closed before the body is
+    /// read. a chunked body fails to read once the decoded size crosses the
+    /// limit, returning that error from the service answers with 413 as well
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.config.max_body_size = size;
+        self
+    }
+
+    /// set whether header names and values are checked strictly, default is true
+    ///
+    /// a header name that's not a token or a value with control characters
+    /// is answered with `400 Bad Request`, so a header echoed back can't inject
+    /// others. turn it off for legacy clients that send such headers
+    pub fn strict_headers(mut self, strict: bool) -> Self {
+        self.config.strict_headers = strict;
+        self
+    }
+
+    /// set the initial sizes of the per connection buffers, default is 32K, 128K and 32K
+    ///
+    /// `req_initial` is the buffer the requests are read into, it's also the
+    /// size it's grown by when it's almost full unless `read_chunk_size` is
+    /// set. `rsp_initial` is the buffer
+    /// the encoded responses are queued in until they are written. `body_initial`
+    /// is the buffer behind `Response::body_mut`. each connection keeps all three,
+    /// so smaller sizes save memory with many connections while larger ones
+    /// avoid growing them for large headers, pipelined batches or bodies
+    pub fn buffer_config(
+        mut self,
+        req_initial: usize,
+        rsp_initial: usize,
+        body_initial: usize,
+    ) -> Self {
+        self.config.req_buf_size = req_initial;
+        self.config.rsp_buf_size = rsp_initial;
+        self.config.body_buf_size = body_initial;
+        self
+    }
+
+    /// keep the buffers of up to `max` closed connections for the new ones, default is none
+    ///
+    /// with many short connections it saves allocating the three buffers of
+    /// `buffer_config` for each of them. a buffer that grew over 4 times its
+    /// initial size is freed instead of kept, so call this after `buffer_config`
+    pub fn buffer_pool(mut self, max: usize) -> Self {
+        let c = &self.config;
+        let largest = c.req_buf_size.max(c.rsp_buf_size).max(c.body_buf_size);
+        self.config.buffer_pool = Some(BufferPool::new(max, largest * 4));
+        self
+    }
+
+    /// set how much the request buffer is grown by for a read, default is the
+    /// initial size of the buffer
+    ///
+    /// it's the baseline, when reads keep filling all the space the growth is
+    /// doubled each time up to 16 times the size, so a large upload takes fewer
+    /// reads. it goes back to the baseline after a read that doesn't fill it
+    pub fn read_chunk_size(mut self, size: usize) -> Self {
+        self.config.read_chunk_size = Some(size);
+        self
+    }
+
+    /// set the most bytes of responses queued before they are written, default is no limit
+    ///
+    /// the responses of a batch of requests are written together once they're
+    /// all encoded. with a limit they're written as soon as they grow past it,
+    /// and a larger body is written from the body buffer instead of copied,
+    /// so a huge response doesn't double the memory it takes
+    pub fn response_buffer_limit(mut self, limit: Option<usize>) -> Self {
+        self.config.rsp_buf_limit = limit;
+        self
+    }
+
+    /// set the timeout for reading the requests, default is no timeout
+    ///
+    /// the timeout applies to each read while a request is incomplete, the
+    /// reads of the body too unless `Request::set_deadline` overrides it.
+    /// a connection that stalls mid-request longer than this is dropped
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// set the timeout for writing the response, default is no timeout
+    ///
+    /// the timeout applies to each write while the client isn't reading,
+    /// a connection that can't take the response longer than this is dropped
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// set the timeout for waiting for the next request, default is no timeout
+    ///
+    /// it applies while a connection has no request in progress, like between
+    /// keep-alive requests, a connection idle longer than this is closed
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    /// set the value of the `Server` header, default is `may`
+    ///
+    /// with `None` the header is omitted, a `Server` header set by the
+    /// service is always sent as it is
+    pub fn server_name(mut self, name: Option<String>) -> Self {
+        self.config.server_name = name;
+        self
+    }
+
+    /// set the callback invoked after each response is written
+    ///
+    /// it's called from the connection coroutine so it should not block for long
+    pub fn access_log<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&AccessLog) + Send + Sync + 'static,
+    {
+        self.config.access_log = Some(Box::new(f));
+        self
+    }
+
+    /// set the callback invoked for the io errors that end a connection or an accept
+    ///
+    /// it replaces the `log` output, the default logs with `error!` and skips
+    /// the clients that reset or close the connection, the callback gets those too
+    pub fn on_io_error<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.config.on_io_error = Some(Box::new(f));
+        self
+    }
+
+    /// set `TCP_NODELAY` on the accepted connections, default is true
+    ///
+    /// it disables the Nagle's algorithm so small responses are not delayed,
+    /// set it to false to leave the algorithm on for bulk transfers
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.config.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// cork the connections while the responses are written, default is off
+    ///
+    /// with `TCP_CORK` the head, the body and the chunks written one after
+    /// another go out in full packets, the stream is uncorked once the
+    /// responses of a batch are written and on each flush of a streamed body.
+    /// it only has an effect on linux tcp connections
+    pub fn tcp_cork(mut self, cork: bool) -> Self {
+        self.config.tcp_cork = cork;
+        self
+    }
+
+    /// turn on `SO_KEEPALIVE` on the accepted connections, default is off
+    ///
+    /// the system probes a connection idle for the given time, so a peer that
+    /// is gone without closing, like one behind a NAT that dropped it, is
+    /// detected and its coroutine ends. the interval and the number of the
+    /// probes are the system defaults. it's only set on unix
+    pub fn socket_keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.config.socket_keepalive = idle;
+        self
+    }
+
+    /// set how many requests are served on a connection, default is unlimited
+    ///
+    /// the last response is sent with `Connection: close`, so a client behind
+    /// a load balancer reconnects from time to time and the load is spread again
+    pub fn max_requests_per_connection(mut self, max: usize) -> Self {
+        self.config.max_requests = Some(max.max(1));
+        self
+    }
+
+    /// set the stack size of the connection coroutines, default is the one of may
+    ///
+    /// the service runs on that stack, a handler that recurses deeply or keeps
+    /// large buffers on the stack needs more than the default
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.config.stack_size = Some(size);
+        self
+    }
+
+    /// limit the connections served at once, default is unlimited
+    ///
+    /// `Overload::Wait` stops accepting until a connection closes, so the new
+    /// ones wait in the listen backlog. `Overload::Reject` answers a new
+    /// connection with `503 Service Unavailable` and a `Retry-After` and closes it
+    pub fn max_connections(mut self, max: usize, overload: Overload) -> Self {
+        self.config.max_connections = Some(ConnectionLimit {
+            slots: Arc::new(Semphore::new(max.max(1))),
+            overload,
+        });
+        self
+    }
+
+    /// answer `OPTIONS *` with the methods in the `Allow` header, default is passing it to the service
+    ///
+    /// the methods are also sent in the `Allow` of a rejected `TRACE`
+    pub fn options_allow(mut self, methods: &[Method]) -> Self {
+        let methods: Vec<_> = methods.iter().map(|m| m.as_str()).collect();
+        self.config.options_allow = Some(methods.join(", "));
+        self
+    }
+
+    /// set the status of a response the service sets no status on, default is `200 Ok`
+    pub fn default_status(mut self, status: DefaultStatus) -> Self {
+        self.config.default_status = status;
+        self
+    }
+
+    /// set how a `TRACE` request is handled, default is passing it to the service
+    pub fn trace(mut self, trace: Trace) -> Self {
+        self.config.trace = trace;
+        self
+    }
+
+    /// the counters of the server, they are only updated once this is called
+    ///
+    /// the same counters are returned each time, they are shared by all the
+    /// connections. call it before starting the server
+    pub fn metrics(&mut self) -> Arc<Metrics> {
+        self.config
+            .metrics
+            .get_or_insert_with(Default::default)
+            .clone()
+    }
+
+    /// read the PROXY protocol header at the start of each connection, default is false
+    ///
+    /// a load balancer like HAProxy or AWS NLB sends the v1 or v2 header with
+    /// the address of the client, it's then the `Request::peer_addr`. a
+    /// connection without a valid header is closed, so only turn it on when
+    /// all the connections come through the load balancer
+    pub fn accept_proxy_protocol(mut self, on: bool) -> Self {
+        self.config.proxy_protocol = on;
+        self
+    }
+
+    /// set the backlog of the listening socket, default is the 128 of the standard library
+    ///
+    /// the kernel queues this many connections not accepted yet, a larger one
+    /// keeps a burst of connections from being dropped. the kernel may cap
+    /// it, like with `net.core.somaxconn` on linux. it's only applied on unix
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.config.backlog = Some(backlog);
+        self
+    }
+
+    /// set the longest wait before accepting again after an error, default is 1 second
+    ///
+    /// a failed accept, like when the process runs out of file descriptors,
+    /// is retried after 5ms, and the wait is doubled up to this while the
+    /// accepts keep failing so the loop doesn't spin. it's reset by a success
+    pub fn max_accept_backoff(mut self, max: Duration) -> Self {
+        self.config.max_accept_backoff = max;
+        self
+    }
+
+    /// tag each request with an id, default is false
+    ///
+    /// the id is the `X-Request-Id` sent by the client or a new one, it's
+    /// reached by `Request::request_id`, echoed in the `X-Request-Id` of the
+    /// response and passed to the access log
+    pub fn request_id(mut self, enabled: bool) -> Self {
+        self.config.request_id = enabled;
+        self
+    }
+
+    /// set the body size below which `Response::auto_compress` is skipped, default is 1024
+    ///
+    /// compressing tiny bodies costs more than the bytes it saves
+    #[cfg(feature = "compress")]
+    pub fn compress_min_size(mut self, size: usize) -> Self {
+        self.config.compress_min_size = size;
+        self
+    }
+}
+
+/// the access log entries of the requests served in a batch
+type Logged<'r> = Vec<(
+    Method<'r>,
+    &'r str,
+    &'static str,
+    usize,
+    Instant,
+    Option<Cow<'r, str>>,
+)>;
+
+fn log_requests(config: &Config, logged: Logged, peer_addr: Option<SocketAddr>) {
+    if let Some(log) = config.access_log.as_ref() {
+        for (method, path, status, bytes, start, request_id) in logged {
+            log(&AccessLog {
+                method,
+                path,
+                status,
+                bytes,
+                elapsed: start.elapsed(),
+                peer_addr,
+                request_id: request_id.as_deref(),
+            });
+        }
+    }
+}
+
+/// the generator of the request ids of a connection
+///
+/// a random seed for each connection and a counter, so the ids are cheap
+/// and not repeated
+struct RequestIds {
+    seed: u64,
+    count: u64,
+}
+
+impl RequestIds {
+    // `BuildHasher::hash_one` is newer than the rust the crate supports
+    #[allow(clippy::manual_hash_one)]
+    fn new() -> Self {
+        RequestIds {
+            seed: {
+                let mut hasher = RandomState::new().build_hasher();
+                Instant::now().hash(&mut hasher);
+                hasher.finish()
+            },
+            count: 0,
+        }
+    }
+
+    /// the id sent by the client if it looks sane, or a new one
+    fn get<'r>(&mut self, req: &Request<'_, 'r, '_>) -> Cow<'r, str> {
+        let sent = req
+            .header("x-request-id")
+            .filter(|id| (1..=200).contains(&id.len()) && id.iter().all(|b| b.is_ascii_graphic()))
+            .and_then(|id| std::str::from_utf8(id).ok());
+        match sent {
+            Some(id) => Cow::Borrowed(id),
+            None => {
+                self.count += 1;
+                Cow::Owned(format!("{:016x}{:08x}", self.seed, self.count))
+            }
+        }
+    }
+}
+
+/// the free space below which a buffer is grown
+const MIN_BUF_SPACE: usize = 512;
+
+/// how many times the read chunk size grows while the reads fill the buffer
+const MAX_READ_GROWTH: usize = 16;
+
+/// make sure there's some free space in the buffer, growing it to `size` when it's low
+fn reserve_buf(buf: &mut BytesMut, size: usize) {
+    let remaining = buf.capacity() - buf.len();
+    if remaining < MIN_BUF_SPACE {
+        buf.reserve(size.max(MIN_BUF_SPACE) - remaining);
+    }
+}
+
+/// the stream corked while the responses are written, it's uncorked when
+/// dropped so no return leaves the data held back
+struct Cork<'s>(&'s Conn);
+
+impl<'s> Cork<'s> {
+    fn new(stream: &'s Conn) -> Self {
+        let _ = stream.borrow().set_cork(true);
+        Cork(stream)
+    }
+}
+
+impl Drop for Cork<'_> {
+    fn drop(&mut self) {
+        if let Ok(stream) = self.0.try_borrow() {
+            let _ = stream.set_cork(false);
+        }
+    }
+}
+
 // #[cfg(unix)]
-fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
-    let mut req_buf = BytesMut::with_capacity(4096 * 8);
-    let mut rsp_buf = BytesMut::with_capacity(4096 * 32);
-    let mut body_buf = BytesMut::with_capacity(4096 * 8);
-    stream.set_nonblocking(true).unwrap();
-    let finder = FinderRev::new(b"\r\n\r\n");
+pub(crate) fn each_connection_loop<S: Transport + 'static, T: HttpService>(
+    stream: S,
+    mut service: T,
+    config: &Config,
+    source: Option<SocketAddr>,
+) {
+    let mut bufs = PooledBuffers::get(config.buffer_pool.as_ref(), || Buffers {
+        req: BytesMut::with_capacity(config.req_buf_size),
+        rsp: BytesMut::with_capacity(config.rsp_buf_size),
+        body: BytesMut::with_capacity(config.body_buf_size),
+    });
+    let Buffers {
+        req: req_buf,
+        rsp: rsp_buf,
+        body: body_buf,
+    } = &mut *bufs;
+    let base_chunk = config.read_chunk_size.unwrap_or(config.req_buf_size);
+    // doubled after each read that fills the buffer
+    let mut read_chunk = base_chunk;
+    // a transport of `start_on` may not support it, close the connection then
+    t!(config, stream.set_nonblocking(true));
+    // the socket timeout currently set, it's switched between the idle and the read timeout
+    let mut timeout = None;
+    // the same for all the requests on the connection, the client behind a proxy if there's one
+    let peer_addr = source.or_else(|| stream.peer_addr().ok());
+    let mut context = ConnContext::default();
+    let mut request_ids = config.request_id.then(RequestIds::new);
+    // the requests served on the connection
+    let mut served = 0;
+    let guard = config.shutdown.as_ref().map(|s| s.register());
+    let _active = config.metrics.as_ref().map(|m| m.connection());
+    let finder = Finder::new(b"\r\n\r\n");
+    if config.write_timeout.is_some() {
+        t!(config, stream.set_write_timeout(config.write_timeout));
+    }
+    // shared with the request body and the response
+    let stream = RefCell::new(stream);
     loop {
         #[cfg(unix)]
-        stream.reset_io();
+        stream.borrow().reset_io();
 
         loop {
             // read the socket for requests
-            let remaining = req_buf.capacity() - req_buf.len();
-            if remaining < 512 {
-                req_buf.reserve(4096 * 8 - remaining);
+            reserve_buf(req_buf, read_chunk);
+
+            // no request in progress, waiting for a new one
+            let wanted = if req_buf.is_empty() {
+                config.idle_timeout
+            } else {
+                config.read_timeout
+            };
+            if wanted != timeout {
+                t!(config, stream.borrow().set_read_timeout(wanted));
+                timeout = wanted;
             }
 
             let buf = req_buf.chunk_mut();
             let read_buf = unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr(), buf.len()) };
-            match stream.read(read_buf) {
+            let space = read_buf.len();
+            let res = read_wait(&mut *stream.borrow_mut(), read_buf, timeout);
+            match res {
                 Ok(n) => {
                     if n == 0 {
                         //connection was closed
                         return;
                     } else {
                         unsafe { req_buf.advance_mut(n) };
+                        read_chunk = if n == space {
+                            (read_chunk * 2).min(base_chunk * MAX_READ_GROWTH)
+                        } else {
+                            base_chunk
+                        };
+                        if let Some(metrics) = &config.metrics {
+                            metrics.read(n);
+                        }
+                        if let Some(guard) = guard.as_ref() {
+                            guard.set_idle(false);
+                        }
 
-                        if finder.rfind(&req_buf).is_some() {
+                        // the bytes before were already searched, the end of the
+                        // head may only start in the last 3 of them
+                        let searched = req_buf.len() - n;
+                        if finder
+                            .find(&req_buf[searched.saturating_sub(3)..])
+                            .is_some()
+                        {
                             break;
                         }
+                        if req_buf.len() > config.max_header_bytes {
+                            // answer the client before closing the connection
+                            let rejection = Rejection::header_too_large();
+                            let mut rsp = rejection_rsp(&rejection, body_buf);
+                            rsp.set_server(config.server_name.as_deref());
+                            response::encode(rsp, rsp_buf);
+                            let mut stream = stream.borrow_mut();
+                            let _ = write_wait(&mut *stream, rsp_buf)
+                                .and_then(|_| flush_wait(&mut *stream));
+                            return;
+                        }
                     }
                 }
                 Err(err) => {
-                    if err.kind() == io::ErrorKind::WouldBlock {
-                        // error!("Unexpected EOF");
-                        return;
-                    } else if err.kind() == io::ErrorKind::ConnectionReset
-                        || err.kind() == io::ErrorKind::UnexpectedEof
-                    {
-                        // info!("http server read req: connection closed");
-                        return;
-                    }
-                    error!("call = {:?}\nerr = {:?}", stringify!($e), err);
+                    io_error(config, &err, "read request");
                     return;
                 }
             }
         }
 
-        let remaining = rsp_buf.capacity() - rsp_buf.len();
-        if remaining < 512 {
-            rsp_buf.reserve(4096 * 32 - remaining);
-        }
+        reserve_buf(rsp_buf, config.rsp_buf_size);
 
-        let mut headers: [httparse::Header; 16] = unsafe {
-            let h: [MaybeUninit<httparse::Header>; 16] = MaybeUninit::uninit().assume_init();
-            std::mem::transmute(h)
+        // a request with more headers is parsed again with them on the heap
+        let mut inline_headers = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+        let mut heap_headers = Vec::new();
+        let options = ParseOptions {
+            max_body_size: config.max_body_size,
+            strict_headers: config.strict_headers,
+            read_timeout: config.read_timeout,
         };
 
-        // prepare the requests
-        if let Some(req) = t!(request::decode(&req_buf, &mut headers, &mut stream)) {
-            let mut rsp = Response::new(&mut body_buf);
-            if let Err(e) = service.call(req, &mut rsp) {
-                let err_rsp = internal_error_rsp(e, &mut body_buf);
-                response::encode(err_rsp, &mut rsp_buf);
-            } else {
-                response::encode(rsp, &mut rsp_buf);
-            }
-        }
-
-        req_buf.clear();
+        // prepare the pipelined requests, the responses are queued in the same order
+        let mut keep_alive = true;
+        // the bytes of the requests that are done
+        let mut consumed = 0;
+        // the access log entries of the decoded requests
+        let mut logged = Logged::new();
+        // the protocol the connection switches to after the last response
+        let mut upgraded = None;
+        // set with the first request of the batch
+        let mut cork = None;
+        while keep_alive && consumed < req_buf.len() {
+            let body_state = BodyState {
+                timeout: Cell::new(timeout),
+                ..BodyState::default()
+            };
+            let buf = &req_buf[consumed..];
+            let head = request::parse_head_spill(
+                buf,
+                &mut inline_headers,
+                &mut heap_headers,
+                config.max_headers,
+                options,
+            );
+            let decoded = head
+                .map(|head| {
+                    head.map(|head| {
+                        request::from_head(
+                            buf,
+                            head,
+                            &stream,
+                            peer_addr,
+                            options,
+                            &body_state,
+                            &mut context,
+                        )
+                    })
+                })
+                .and_then(|req| match req {
+                    // the read loop only checks the size of a head it has not found the end of
+                    Some(req) if req.head_len() > config.max_header_bytes => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        Rejection::header_too_large(),
+                    )),
+                    req => Ok(req),
+                });
+            match decoded {
+                Ok(Some(mut req)) => {
+                    if config.tcp_cork && cork.is_none() {
+                        cork = Some(Cork::new(&stream));
+                    }
+                    // a streamed response is written directly, send the previous ones first
+                    if !rsp_buf.is_empty() {
+                        t!(config, write_wait(&mut *stream.borrow_mut(), rsp_buf));
+                        rsp_buf.clear();
+                    }
 
-        let len = rsp_buf.len();
-        let mut written = 0;
-        while written < len {
-            match stream.write(&rsp_buf[written..]) {
-                Ok(n) => {
-                    if n == 0 {
-                        return;
+                    // let the client know the connection closes when shutting down
+                    // or on the last request allowed
+                    served += 1;
+                    keep_alive = req.is_keep_alive()
+                        && !config.is_stopping()
+                        && config.max_requests.map_or(true, |max| served < max);
+                    req.set_keep_alive(keep_alive);
+                    let start = Instant::now();
+                    let method = req.method();
+                    let version = req.version();
+                    let path = req.path();
+                    let head_len = req.head_len();
+                    let request_id = request_ids.as_mut().map(|ids| ids.get(&req));
+                    if let Some(id) = &request_id {
+                        req.set_request_id(id.clone());
+                    }
+                    let mut rsp = Response::new(body_buf);
+                    if let Some(id) = &request_id {
+                        rsp.add_header("X-Request-Id", id);
+                    }
+                    rsp.set_keep_alive(keep_alive);
+                    rsp.set_version(version);
+                    rsp.set_server(config.server_name.as_deref());
+                    rsp.set_stream(&stream);
+                    rsp.set_corked(cork.is_some());
+                    // the service answers a HEAD like a GET, the body is dropped
+                    let head = req.method() == Method::Head;
+                    rsp.set_head(head);
+                    if matches!(req.method(), Method::Get | Method::Head) {
+                        rsp.set_if_none_match(req.header("if-none-match"));
+                    }
+                    #[cfg(feature = "compress")]
+                    rsp.set_compression(
+                        crate::compress::negotiate(req.header_all("accept-encoding")),
+                        config.compress_min_size,
+                    );
+                    let res = if builtin_rsp(config, &req, &mut rsp) {
+                        // the same as the service dropping it, an unread body is drained
+                        drop(req);
+                        Ok(())
                     } else {
-                        written += n;
+                        panic::catch_unwind(AssertUnwindSafe(|| service.call(req, &mut rsp)))
+                            .unwrap_or_else(|payload| {
+                                // the service may be left in a broken state, don't reuse the connection
+                                keep_alive = false;
+                                Err(panic_error(payload))
+                            })
+                    };
+                    // the deadline of the route passed, the answer is too late
+                    let late = body_state
+                        .deadline
+                        .get()
+                        .is_some_and(|d| Instant::now() >= d);
+                    let res = match res {
+                        _ if late && !rsp.is_chunked() => Err(request::deadline_passed()),
+                        res => res,
+                    };
+                    let mut rsp = match res {
+                        Ok(()) => {
+                            keep_alive = rsp.is_keep_alive();
+                            rsp
+                        }
+                        // the client closed before sending the whole body, there's
+                        // no one to answer
+                        Err(_) if body_state.truncated.get() => return,
+                        Err(e) if rsp.is_chunked() => {
+                            // the head is already sent, the client sees an incomplete body
+                            if !is_disconnect(&e) {
+                                error!("error in service: err = {:?}", e);
+                            }
+                            return;
+                        }
+                        Err(e) => {
+                            // the service may have asked to close before failing
+                            keep_alive &= rsp.is_keep_alive();
+                            let mut err_rsp = match Rejection::from_io(&e) {
+                                // like a too large chunked body
+                                Some(rejection) => {
+                                    keep_alive = false;
+                                    rejection_rsp(rejection, body_buf)
+                                }
+                                None => {
+                                    let mut err_rsp = internal_error_rsp(&mut service, e, body_buf);
+                                    err_rsp.set_keep_alive(keep_alive);
+                                    err_rsp
+                                }
+                            };
+                            err_rsp.set_head(head);
+                            err_rsp.set_version(version);
+                            err_rsp.set_server(config.server_name.as_deref());
+                            if let Some(id) = &request_id {
+                                err_rsp.add_header("X-Request-Id", id);
+                            }
+                            err_rsp
+                        }
+                    };
+                    // the body reads may have switched it
+                    timeout = body_state.timeout.get();
+                    match body_state.end.get() {
+                        Some(body_len) => consumed += head_len + body_len,
+                        None => {
+                            // the body is not read to the end, the next request can't be found
+                            keep_alive = false;
+                            rsp.set_keep_alive(false);
+                        }
                     }
-                }
-                Err(err) => {
-                    if err.kind() == io::ErrorKind::WouldBlock {
+                    if config.default_status == DefaultStatus::NoContent {
+                        rsp.set_no_content_default();
+                    }
+                    let upgrade = rsp.take_upgrade();
+                    let code = rsp.code();
+                    let sent = rsp.bytes_sent();
+                    let encoded = match config.rsp_buf_limit {
+                        Some(limit) => {
+                            let encoded = t!(
+                                config,
+                                response::encode_bounded(rsp, rsp_buf, limit, &stream)
+                            );
+                            // the queued responses are not kept past the limit either
+                            if rsp_buf.len() > limit {
+                                t!(config, write_wait(&mut *stream.borrow_mut(), rsp_buf));
+                                rsp_buf.clear();
+                            }
+                            encoded
+                        }
+                        None => {
+                            let encoded = rsp_buf.len();
+                            response::encode(rsp, rsp_buf);
+                            rsp_buf.len() - encoded
+                        }
+                    };
+                    let bytes = sent + encoded;
+                    if let Some(metrics) = &config.metrics {
+                        metrics.response(code, bytes);
+                    }
+                    if config.access_log.is_some() {
+                        logged.push((method, path, code, bytes, start, request_id));
+                    }
+
+                    if upgrade.is_some() {
+                        // the request borrows the stream until it's dropped
+                        upgraded = upgrade;
                         break;
-                    } else if err.kind() == io::ErrorKind::ConnectionReset
-                        || err.kind() == io::ErrorKind::UnexpectedEof
-                    {
-                        // info!("http server read req: connection closed");
-                        return;
                     }
-                    error!("call = {:?}\nerr = {:?}", stringify!($e), err);
-                    return;
                 }
+                // wait for the rest of the request
+                Ok(None) => break,
+                Err(e) => match Rejection::from_io(&e) {
+                    // answer the client before closing the connection
+                    Some(rejection) => {
+                        keep_alive = false;
+                        let mut rsp = rejection_rsp(rejection, body_buf);
+                        rsp.set_server(config.server_name.as_deref());
+                        let encoded = rsp_buf.len();
+                        response::encode(rsp, rsp_buf);
+                        if let Some(metrics) = &config.metrics {
+                            metrics.response(rejection.code, rsp_buf.len() - encoded);
+                        }
+                    }
+                    None => t!(config, Err(e)),
+                },
             }
         }
-        if written == len {
-            unsafe { rsp_buf.set_len(0) }
-        } else if written > 0 {
-            rsp_buf.advance(written);
+
+        if let Some(on_upgrade) = upgraded {
+            // the rest of the connection is not http, hand it over once the head is sent
+            {
+                let mut stream = stream.borrow_mut();
+                t!(config, write_wait(&mut *stream, rsp_buf));
+                t!(config, flush_wait(&mut *stream));
+            }
+            drop(cork);
+            log_requests(config, logged, peer_addr);
+            let rest = BytesMut::from(&req_buf[consumed..]);
+            on_upgrade(Upgraded::new(Box::new(stream.into_inner()), rest));
+            return;
+        }
+
+        // write all the responses before reading again, a full socket parks the
+        // coroutine until it's writable and the write goes on from where it
+        // stopped, so a large response is never held back behind the next request
+        t!(config, write_wait(&mut *stream.borrow_mut(), rsp_buf));
+        rsp_buf.clear();
+        drop(cork);
+
+        log_requests(config, logged, peer_addr);
+        // keep the start of the next request
+        req_buf.advance(consumed);
+
+        if !keep_alive {
+            // the client asked to close the connection
+            let _ = flush_wait(&mut *stream.borrow_mut());
+            return;
+        }
+
+        // the transport may keep some data, like the tls records, the rest
+        // of it is written out by the following reads
+        if let Err(err) = stream.borrow_mut().flush() {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return;
+            }
+        }
+
+        if let Some(guard) = guard.as_ref() {
+            guard.set_idle(true);
         }
 
+        // with an idle timeout the wait is done by the next read
         #[cfg(unix)]
-        stream.wait_io();
+        if config.idle_timeout.is_none() {
+            stream.borrow().wait_io();
+        }
     }
 }
 
@@ -222,10 +1415,7 @@ fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
         }
         unsafe { req_buf.advance_mut(n) };
 
-        let mut headers: [httparse::Header; 16] = unsafe {
-            let h: [MaybeUninit<httparse::Header>; 16] = MaybeUninit::uninit().assume_init();
-            std::mem::transmute(h)
-        };
+        let mut headers = [httparse::EMPTY_HEADER; 16];
 
         // prepare the requests
         while let Some(req) = t!(request::decode(&req_buf, &mut headers, &mut stream)) {
@@ -245,21 +1435,829 @@ fn each_connection_loop<T: HttpService>(mut stream: TcpStream, mut service: T) {
 }
 */
 
-impl<T: HttpService + Clone + Send + Sync + 'static> HttpServer<T> {
+impl<T: HttpService + Clone + Send + Sync + 'static> HttpServerBuilder<T> {
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
-        let listener = TcpListener::bind(addr)?;
-        let service = self.0;
+        let listener = bind_listener(addr, &self.config)?;
+        let accept = tcp_accept(&self.config);
+        let service = self.service;
+        let config = Arc::new(self.config);
         go!(
             coroutine::Builder::new().name("TcpServer".to_owned()),
+            move || serve(listener.incoming(), config, || service.clone(), accept)
+        )
+    }
+
+    /// Spawns the http service on an already listening socket
+    ///
+    /// it's for the socket activation of systemd, which passes the first
+    /// socket as fd 3 (`LISTEN_FDS` has the count), and for handing the
+    /// socket over on a restart. an fd that's not a listening tcp socket is
+    /// an `InvalidInput` error and it's left open, otherwise the server owns it
+    #[cfg(target_os = "linux")]
+    pub fn start_from_fd(
+        self,
+        fd: std::os::unix::io::RawFd,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        let listener = crate::listener::from_fd(fd)?;
+        let accept = tcp_accept(&self.config);
+        let service = self.service;
+        let config = Arc::new(self.config);
+        go!(
+            coroutine::Builder::new().name("TcpServer".to_owned()),
+            move || serve(listener.incoming(), config, || service.clone(), accept)
+        )
+    }
+
+    /// Spawns the http service on `n` listeners bound to the same address
+    ///
+    /// on linux each listener is opened with `SO_REUSEPORT` and has its own
+    /// accept coroutine, so the kernel spreads the accepting over them.
+    /// elsewhere a single listener is used. return the accept coroutines
+    pub fn start_reuseport<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        n: usize,
+    ) -> io::Result<Vec<coroutine::JoinHandle<()>>> {
+        #[cfg(target_os = "linux")]
+        let listeners = {
+            let addr = addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+            let backlog = self
+                .config
+                .backlog
+                .unwrap_or(crate::listener::DEFAULT_BACKLOG);
+            (0..n.max(1))
+                .map(|_| crate::listener::bind_reuseport(addr, backlog))
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        #[cfg(not(target_os = "linux"))]
+        let listeners = {
+            let _ = n;
+            vec![bind_listener(addr, &self.config)?]
+        };
+        self.start_listeners(listeners)
+    }
+
+    /// Spawns the http service on the given port of both the ipv6 and ipv4
+    /// unspecified addresses
+    ///
+    /// on unix a single `[::]` listener with `IPV6_V6ONLY` turned off takes
+    /// the ipv4 connections as mapped addresses. where that is not supported,
+    /// like on openbsd, and on windows, an ipv6 only listener and a `0.0.0.0`
+    /// one are used, so a port of 0 gives them different ports. linux has
+    /// dual stack sockets by default, while the BSDs, macOS and windows don't.
+    /// return the accept coroutines
+    pub fn start_dual_stack(self, port: u16) -> io::Result<Vec<coroutine::JoinHandle<()>>> {
+        let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+        #[cfg(unix)]
+        let listeners = {
+            let backlog = self
+                .config
+                .backlog
+                .unwrap_or(crate::listener::DEFAULT_BACKLOG);
+            match crate::listener::bind_ipv6(port, false, backlog) {
+                Ok(listener) => vec![listener],
+                Err(_) => vec![
+                    crate::listener::bind_ipv6(port, true, backlog)?,
+                    crate::listener::bind(v4, backlog)?,
+                ],
+            }
+        };
+        // the ipv6 sockets are ipv6 only by default
+        #[cfg(not(unix))]
+        let listeners = vec![
+            TcpListener::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))?,
+            TcpListener::bind(v4)?,
+        ];
+        self.start_listeners(listeners)
+    }
+
+    /// spawn an accept coroutine for each listener
+    fn start_listeners(
+        self,
+        listeners: Vec<TcpListener>,
+    ) -> io::Result<Vec<coroutine::JoinHandle<()>>> {
+        let accept = tcp_accept(&self.config);
+        let service = self.service;
+        let config = Arc::new(self.config);
+        listeners
+            .into_iter()
+            .enumerate()
+            .map(|(i, listener)| {
+                let service = service.clone();
+                let config = config.clone();
+                let accept = accept.clone();
+                go!(
+                    coroutine::Builder::new().name(format!("TcpServer-{}", i)),
+                    move || serve(listener.incoming(), config, || service.clone(), accept)
+                )
+            })
+            .collect()
+    }
+
+    /// Spawns the http service, binding to a unix domain socket at the given path
+    ///
+    /// a stale socket file left at the path is removed before binding, the
+    /// socket file is removed again when the server coroutine exits
+    #[cfg(unix)]
+    pub fn start_unix<P: AsRef<Path>>(self, path: P) -> io::Result<coroutine::JoinHandle<()>> {
+        let path = path.as_ref().to_owned();
+        remove_stale_socket(&path)?;
+        let listener = UnixListener::bind(&path)?;
+        let service = self.service;
+        let config = Arc::new(self.config);
+        go!(
+            coroutine::Builder::new().name("UnixServer".to_owned()),
             move || {
-                for stream in listener.incoming() {
-                    let stream = t_c!(stream);
-                    let service = service.clone();
-                    go!(move || each_connection_loop(stream, service));
-                }
+                // also removed when the coroutine is cancelled
+                let _socket = SocketFile(path);
+                serve(listener.incoming(), config, || service.clone(), Ok)
+            }
+        )
+    }
+
+    /// Spawns the https service, binding to the given address
+    ///
+    /// each accepted connection does the tls handshake with the given
+    /// `rustls` config in its own coroutine before serving the requests
+    #[cfg(feature = "tls")]
+    pub fn start_tls<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        tls_config: impl Into<Arc<rustls::ServerConfig>>,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        let listener = bind_listener(addr, &self.config)?;
+        let tls_config = tls_config.into();
+        let read_timeout = self.config.read_timeout;
+        let tcp = tcp_accept(&self.config);
+        let service = self.service;
+        let config = Arc::new(self.config);
+        go!(
+            coroutine::Builder::new().name("TlsServer".to_owned()),
+            move || {
+                let accept = move |stream: TcpStream| {
+                    let stream = tcp(stream)?;
+                    stream.set_read_timeout(read_timeout)?;
+                    crate::tls::TlsStream::accept(stream, tls_config.clone())
+                };
+                serve(listener.incoming(), config, || service.clone(), accept)
+            }
+        )
+    }
+
+    /// Spawns the http service on the connections accepted by the listener
+    ///
+    /// the streams are served as the listener returns them, the socket
+    /// options like `tcp_nodelay` are not set
+    pub fn start_on<L: Listener>(self, listener: L) -> io::Result<coroutine::JoinHandle<()>> {
+        let service = self.service;
+        let config = Arc::new(self.config);
+        go!(
+            coroutine::Builder::new().name("Server".to_owned()),
+            move || {
+                let incoming = std::iter::repeat_with(|| listener.accept());
+                serve(incoming, config, || service.clone(), Ok)
             }
         )
     }
+
+    /// Spawns the http service like `start`, also return a `Shutdown` token
+    ///
+    /// after calling `Shutdown::shutdown` the server stops accepting and the
+    /// returned coroutine completes once the existing connections are drained
+    pub fn start_with_shutdown<L: ToSocketAddrs>(
+        mut self,
+        addr: L,
+    ) -> io::Result<(coroutine::JoinHandle<()>, Shutdown)> {
+        let listener = bind_listener(addr, &self.config)?;
+        let shutdown = Shutdown::new(listener.local_addr()?);
+        self.config.shutdown = Some(shutdown.state());
+        let service = self.service;
+        let config = Arc::new(self.config);
+        let accept = tcp_accept(&config);
+        let server = go!(
+            coroutine::Builder::new().name("TcpServer".to_owned()),
+            move || serve(listener.incoming(), config, || service.clone(), accept)
+        )?;
+        Ok((server, shutdown))
+    }
+
+    /// run the server like `start_with_shutdown` until a SIGINT or SIGTERM
+    ///
+    /// the signal shuts the server down gracefully, the calling thread is
+    /// blocked until the connections are drained. a second signal exits the
+    /// process without waiting. it installs the signal handler of the process,
+    /// so it fails if there's already one
+    #[cfg(feature = "signal")]
+    pub fn run_until_ctrl_c<L: ToSocketAddrs>(self, addr: L) -> io::Result<()> {
+        let (server, shutdown) = self.start_with_shutdown(addr)?;
+        let on_signal = shutdown.clone();
+        let mut signaled = false;
+        let handler = ctrlc::set_handler(move || {
+            if signaled {
+                std::process::exit(130);
+            }
+            signaled = true;
+            info!("shutting down, waiting for the connections to finish");
+            on_signal.shutdown(None);
+        });
+        if let Err(err) = handler {
+            shutdown.shutdown(Some(Duration::ZERO));
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+        server
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "the server coroutine panicked"))
+    }
+}
+
+// the servers started with the default settings
+impl<T: HttpService + Clone + Send + Sync + 'static> HttpServer<T> {
+    /// see `HttpServerBuilder::start`
+    pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
+        self.builder().start(addr)
+    }
+
+    /// see `HttpServerBuilder::start_with_shutdown`
+    pub fn start_with_shutdown<L: ToSocketAddrs>(
+        self,
+        addr: L,
+    ) -> io::Result<(coroutine::JoinHandle<()>, Shutdown)> {
+        self.builder().start_with_shutdown(addr)
+    }
+
+    /// see `HttpServerBuilder::start_tls`
+    #[cfg(feature = "tls")]
+    pub fn start_tls<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        tls_config: impl Into<Arc<rustls::ServerConfig>>,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        self.builder().start_tls(addr, tls_config)
+    }
+
+    /// see `HttpServerBuilder::start_unix`
+    #[cfg(unix)]
+    pub fn start_unix<P: AsRef<Path>>(self, path: P) -> io::Result<coroutine::JoinHandle<()>> {
+        self.builder().start_unix(path)
+    }
+
+    /// see `HttpServerBuilder::start_reuseport`
+    pub fn start_reuseport<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        n: usize,
+    ) -> io::Result<Vec<coroutine::JoinHandle<()>>> {
+        self.builder().start_reuseport(addr, n)
+    }
+
+    /// see `HttpServerBuilder::start_dual_stack`
+    pub fn start_dual_stack(self, port: u16) -> io::Result<Vec<coroutine::JoinHandle<()>>> {
+        self.builder().start_dual_stack(port)
+    }
+
+    /// see `HttpServerBuilder::start_on`
+    pub fn start_on<L: Listener>(self, listener: L) -> io::Result<coroutine::JoinHandle<()>> {
+        self.builder().start_on(listener)
+    }
+
+    /// see `HttpServerBuilder::run_until_ctrl_c`
+    #[cfg(feature = "signal")]
+    pub fn run_until_ctrl_c<L: ToSocketAddrs>(self, addr: L) -> io::Result<()> {
+        self.builder().run_until_ctrl_c(addr)
+    }
+
+    /// see `HttpServerBuilder::start_from_fd`
+    #[cfg(target_os = "linux")]
+    pub fn start_from_fd(
+        self,
+        fd: std::os::unix::io::RawFd,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        self.builder().start_from_fd(fd)
+    }
+}
+
+/// remove the file at the path if it's a socket left by a previous run
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        // anything else is left for the bind to fail on
+        _ => Ok(()),
+    }
+}
+
+/// the socket file of a unix listener, removed when dropped
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test;
+    use crate::test::slow::{self, SlowStream, Step};
+
+    #[derive(Clone)]
+    struct Hello;
+
+    impl HttpService for Hello {
+        fn call(&mut self, _req: Request, rsp: &mut Response) -> io::Result<()> {
+            rsp.body("hello");
+            Ok(())
+        }
+    }
+
+    /// a request with a head of `len` bytes
+    fn padded_request(len: usize) -> Vec<u8> {
+        let mut req = b"GET / HTTP/1.1\r\nHost: a\r\nX-Pad: ".to_vec();
+        let pad = len - req.len() - 4;
+        req.resize(req.len() + pad, b'a');
+        req.extend_from_slice(b"\r\n\r\n");
+        assert_eq!(req.len(), len);
+        req
+    }
+
+    fn is_431(rsp: &[u8]) -> bool {
+        rsp.starts_with(b"HTTP/1.1 431 Request Header Fields Too Large\r\n")
+    }
+
+    #[test]
+    fn head_at_max_header_bytes_is_served() {
+        let server = HttpServer(Hello).max_header_bytes(200);
+        let rsp = test::serve_with(server, &padded_request(200));
+        assert!(rsp.starts_with(b"HTTP/1.1 200 Ok\r\n"));
+        assert!(rsp.ends_with(b"\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn head_over_max_header_bytes_in_one_read_is_431() {
+        // the whole head, end included, is in the first read
+        let server = HttpServer(Hello).max_header_bytes(200);
+        let rsp = test::serve_with(server, &padded_request(201));
+        assert!(is_431(&rsp));
+        assert!(!rsp.ends_with(b"hello"));
+    }
+
+    #[test]
+    fn head_over_max_header_bytes_without_an_end_is_431() {
+        let mut req = padded_request(400);
+        req.truncate(300);
+        let rsp = test::serve_with(HttpServer(Hello).max_header_bytes(200), &req);
+        assert!(is_431(&rsp));
+    }
+
+    #[test]
+    fn pipelined_head_over_max_header_bytes_is_431() {
+        let mut req = padded_request(100);
+        req.extend_from_slice(&padded_request(201));
+        let rsp = test::serve_with(HttpServer(Hello).max_header_bytes(200), &req);
+        let rsp = String::from_utf8(rsp).unwrap();
+        let (first, second) = rsp.split_once("hello").unwrap();
+        assert!(first.starts_with("HTTP/1.1 200 Ok\r\n"));
+        assert!(is_431(second.as_bytes()));
+    }
+
+    #[test]
+    fn default_max_header_bytes_is_64_kib() {
+        let rsp = test::serve(Hello, &padded_request(64 * 1024));
+        assert!(rsp.starts_with(b"HTTP/1.1 200 Ok\r\n"));
+        let rsp = test::serve(Hello, &padded_request(64 * 1024 + 1));
+        assert!(is_431(&rsp));
+    }
+
+    struct EchoId;
+
+    impl HttpService for EchoId {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            rsp.body_vec(format!("{:?}", req.request_id()).into_bytes());
+            Ok(())
+        }
+    }
+
+    /// the `X-Request-Id` of each response and the id the service saw
+    fn request_ids(rsp: &[u8]) -> Vec<(String, String)> {
+        let rsp = String::from_utf8_lossy(rsp);
+        rsp.split("HTTP/1.1 ")
+            .skip(1)
+            .map(|r| {
+                let (head, body) = r.split_once("\r\n\r\n").unwrap();
+                let id = head.split_once("\r\nX-Request-Id: ").unwrap().1;
+                (id.split("\r\n").next().unwrap().to_owned(), body.to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn request_ids_are_generated_or_kept() {
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n\
+            GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: abc-1\r\n\r\n\
+            GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: a b\r\n\r\n";
+        let rsp = test::serve_with(HttpServer(EchoId).request_id(true), req);
+        let ids = request_ids(&rsp);
+        assert_eq!(ids.len(), 3);
+        for (id, seen) in &ids {
+            assert_eq!(seen, &format!("Some({:?})", id));
+        }
+        assert_eq!(ids[0].0.len(), 24);
+        assert_eq!(ids[1].0, "abc-1");
+        // the id with a space is replaced, the counter goes on
+        assert_eq!(ids[2].0.len(), 24);
+        assert_eq!(ids[0].0[..16], ids[2].0[..16]);
+        assert_ne!(ids[0].0, ids[2].0);
+    }
+
+    #[test]
+    fn request_ids_are_off_by_default() {
+        let rsp = test::serve(EchoId, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let rsp = String::from_utf8(rsp).unwrap();
+        assert!(!rsp.contains("X-Request-Id"));
+        assert!(rsp.ends_with("\r\n\r\nNone"));
+    }
+
+    #[test]
+    fn connection_is_closed_after_max_requests() {
+        let req = "GET / HTTP/1.1\r\nHost: a\r\n\r\n".repeat(3);
+        let server = HttpServer(Hello).max_requests_per_connection(2);
+        let rsp = String::from_utf8(test::serve_with(server, req.as_bytes())).unwrap();
+        let rsps: Vec<_> = rsp.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(rsps.len(), 2);
+        assert!(!rsps[0].contains("\r\nConnection: close\r\n"));
+        assert!(rsps[1].contains("\r\nConnection: close\r\n"));
+    }
+
+    #[test]
+    fn options_star_is_answered_by_the_server() {
+        let server = HttpServer(Hello).options_allow(&[Method::Get, Method::Head]);
+        let rsp = test::serve_with(server, b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n");
+        let rsp = String::from_utf8(rsp).unwrap();
+        assert!(rsp.contains("\r\nAllow: GET, HEAD\r\n"));
+        assert!(!rsp.ends_with("hello"));
+        // passed to the service by default
+        let rsp = test::serve(Hello, b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(rsp.ends_with(b"hello"));
+    }
+
+    #[test]
+    fn trace_is_echoed_without_the_credentials() {
+        let req = b"TRACE /a?b HTTP/1.1\r\nHost: a\r\nCookie: c=1\r\n\
+            Authorization: Basic eDp5\r\nX-Seen: 1\r\n\r\n";
+        let rsp = test::serve_with(HttpServer(Hello).trace(Trace::Echo), req);
+        let rsp = String::from_utf8(rsp).unwrap();
+        assert!(rsp.contains("\r\nContent-Type: message/http\r\n"));
+        let body = rsp.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(body, "TRACE /a?b HTTP/1.1\r\nHost: a\r\nX-Seen: 1\r\n");
+    }
+
+    #[test]
+    fn rejected_trace_is_405() {
+        let server = HttpServer(Hello)
+            .trace(Trace::Reject)
+            .options_allow(&[Method::Get]);
+        let rsp = test::serve_with(server, b"TRACE / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let rsp = String::from_utf8(rsp).unwrap();
+        assert!(rsp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(rsp.contains("\r\nAllow: GET\r\n"));
+    }
+
+    /// a body of a few MB that's not the same after a shift
+    fn large_body() -> Vec<u8> {
+        (0..3 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[derive(Clone)]
+    struct Large;
+
+    impl HttpService for Large {
+        fn call(&mut self, _req: Request, rsp: &mut Response) -> io::Result<()> {
+            rsp.body_vec(large_body());
+            Ok(())
+        }
+    }
+
+    /// the bodies of the responses, each one framed by its `Content-Length`
+    fn bodies(mut rsp: &[u8]) -> Vec<&[u8]> {
+        let finder = memchr::memmem::Finder::new(b"\r\n\r\n");
+        let mut bodies = Vec::new();
+        while !rsp.is_empty() {
+            let end = finder.find(rsp).unwrap() + 4;
+            let head = std::str::from_utf8(&rsp[..end]).unwrap();
+            let len = head
+                .split("\r\n")
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse::<usize>()
+                .unwrap();
+            bodies.push(&rsp[end..end + len]);
+            rsp = &rsp[end + len..];
+        }
+        bodies
+    }
+
+    #[test]
+    fn large_response_to_a_slow_reader() {
+        // a few KB per write, the second request is read with the first response unsent
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let stream = SlowStream::new([Step::Send(req.to_vec())], 7919);
+        let rsp = slow::serve(HttpServer(Large).builder(), stream);
+        let body = large_body();
+        assert!(bodies(&rsp) == [&body[..], &body[..]]);
+    }
+
+    /// a connection of `serve_connections`, what's written is sent when it's closed
+    struct Connection {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        done: may::sync::mpsc::Sender<Vec<u8>>,
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            let _ = self.done.send(std::mem::take(&mut self.output));
+        }
+    }
+
+    impl io::Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl io::Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Connection {
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        #[cfg(unix)]
+        fn reset_io(&self) {}
+
+        #[cfg(unix)]
+        fn wait_io(&self) {}
+    }
+
+    type Incoming = (Vec<u8>, may::sync::mpsc::Sender<Vec<u8>>);
+
+    /// run the accept loop of the server over the connections sent to the returned
+    /// sender, each one is the request bytes and where its output goes
+    fn serve_connections<T: HttpService + Clone + Send + 'static>(
+        server: HttpServerBuilder<T>,
+    ) -> (
+        may::sync::mpsc::Sender<Incoming>,
+        std::thread::JoinHandle<()>,
+    ) {
+        let (tx, rx) = may::sync::mpsc::channel::<Incoming>();
+        let handle = std::thread::spawn(move || {
+            let service = server.service;
+            let incoming = rx.into_iter().map(|(input, done)| {
+                Ok(Connection {
+                    input: io::Cursor::new(input),
+                    output: Vec::new(),
+                    done,
+                })
+            });
+            serve(incoming, Arc::new(server.config), || service.clone(), Ok);
+        });
+        (tx, handle)
+    }
+
+    /// the first request waits for a signal, telling when it has started
+    #[derive(Clone)]
+    struct Gate {
+        started: may::sync::mpsc::Sender<()>,
+        open: Arc<std::sync::Mutex<may::sync::mpsc::Receiver<()>>>,
+    }
+
+    impl HttpService for Gate {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            if req.path() == "/wait" {
+                self.started.send(()).unwrap();
+                self.open.lock().unwrap().recv().unwrap();
+            }
+            rsp.body("done");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn connection_over_max_connections_is_503() {
+        let (started, on_started) = may::sync::mpsc::channel();
+        let (open, on_open) = may::sync::mpsc::channel();
+        let gate = Gate {
+            started,
+            open: Arc::new(std::sync::Mutex::new(on_open)),
+        };
+        let (incoming, server) =
+            serve_connections(HttpServer(gate).max_connections(1, Overload::Reject));
+
+        let (first, first_done) = may::sync::mpsc::channel();
+        let req = b"GET /wait HTTP/1.1\r\nHost: a\r\n\r\n";
+        incoming.send((req.to_vec(), first)).unwrap();
+        on_started.recv().unwrap();
+
+        let (second, second_done) = may::sync::mpsc::channel();
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        incoming.send((req.to_vec(), second.clone())).unwrap();
+        let rsp = String::from_utf8(second_done.recv().unwrap()).unwrap();
+        assert!(rsp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(rsp.contains("\r\nRetry-After: 1\r\n"));
+
+        open.send(()).unwrap();
+        let rsp = first_done.recv().unwrap();
+        assert!(rsp.ends_with(b"\r\n\r\ndone"));
+
+        // the slot is given back once the connection coroutine ends, right
+        // after the output is sent
+        let served = (0..100).any(|_| {
+            incoming.send((req.to_vec(), second.clone())).unwrap();
+            let rsp = second_done.recv().unwrap();
+            if rsp.starts_with(b"HTTP/1.1 503 ") {
+                coroutine::sleep(Duration::from_millis(10));
+                return false;
+            }
+            rsp.ends_with(b"\r\n\r\ndone")
+        });
+        assert!(served);
+        drop(incoming);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connection_over_max_connections_waits() {
+        let (started, on_started) = may::sync::mpsc::channel();
+        let (open, on_open) = may::sync::mpsc::channel();
+        let gate = Gate {
+            started,
+            open: Arc::new(std::sync::Mutex::new(on_open)),
+        };
+        let (incoming, server) =
+            serve_connections(HttpServer(gate).max_connections(1, Overload::Wait));
+
+        let (first, first_done) = may::sync::mpsc::channel();
+        let req = b"GET /wait HTTP/1.1\r\nHost: a\r\n\r\n";
+        incoming.send((req.to_vec(), first)).unwrap();
+        on_started.recv().unwrap();
+
+        let (second, second_done) = may::sync::mpsc::channel();
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        incoming.send((req.to_vec(), second)).unwrap();
+        // not served while the first one is open
+        coroutine::sleep(Duration::from_millis(50));
+        assert!(second_done.try_recv().is_err());
+
+        open.send(()).unwrap();
+        assert!(first_done.recv().unwrap().ends_with(b"\r\n\r\ndone"));
+        assert!(second_done.recv().unwrap().ends_with(b"\r\n\r\ndone"));
+        drop(incoming);
+        server.join().unwrap();
+    }
+
+    #[derive(Clone)]
+    struct EchoPeer;
+
+    impl HttpService for EchoPeer {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            rsp.body_vec(format!("{:?}", req.peer_addr()).into_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn proxy_header_sets_the_peer_addr() {
+        let (incoming, server) =
+            serve_connections(HttpServer(EchoPeer).accept_proxy_protocol(true));
+        let (done, on_done) = may::sync::mpsc::channel();
+        let req = b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        incoming.send((req.to_vec(), done.clone())).unwrap();
+        let rsp = on_done.recv().unwrap();
+        assert!(rsp.ends_with(b"\r\n\r\nSome(192.168.0.1:56324)"));
+
+        // a connection without the header is closed
+        incoming
+            .send((b"GET / HTTP/1.1\r\nHost: a\r\n\r\n".to_vec(), done))
+            .unwrap();
+        assert!(on_done.recv().unwrap().is_empty());
+        drop(incoming);
+        server.join().unwrap();
+    }
+
+    /// a transport that can't be made non-blocking
+    struct Blocking;
+
+    impl io::Read for Blocking {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            panic!("read from a connection that's not set up")
+        }
+    }
+
+    impl io::Write for Blocking {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            panic!("write to a connection that's not set up")
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Blocking {
+        fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        #[cfg(unix)]
+        fn reset_io(&self) {}
+
+        #[cfg(unix)]
+        fn wait_io(&self) {}
+    }
+
+    #[test]
+    fn transport_without_nonblocking_is_closed() {
+        each_connection_loop(Blocking, Hello, &Config::default(), None);
+    }
+
+    /// serve the requests sent in pieces split at `splits`
+    fn serve_split(req: &[u8], splits: &[usize]) -> String {
+        let mut steps = Vec::new();
+        let mut start = 0;
+        for &end in splits.iter().chain([&req.len()]) {
+            steps.push(Step::Send(req[start..end].to_vec()));
+            start = end;
+        }
+        let stream = SlowStream::new(steps, usize::MAX);
+        String::from_utf8(slow::serve(HttpServer(Hello).builder(), stream)).unwrap()
+    }
+
+    #[test]
+    fn head_end_split_across_reads() {
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        // the read before has 1, 2 or 3 bytes of the `\r\n\r\n`
+        for carried in 1..=3 {
+            let rsp = serve_split(req, &[req.len() - 4 + carried]);
+            assert!(
+                rsp.starts_with("HTTP/1.1 200 Ok\r\n"),
+                "{}: {:?}",
+                carried,
+                rsp
+            );
+            assert!(rsp.ends_with("\r\n\r\nhello"), "{}: {:?}", carried, rsp);
+        }
+    }
+
+    #[test]
+    fn head_read_byte_by_byte() {
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let rsp = serve_split(req, &(1..req.len()).collect::<Vec<_>>());
+        assert_eq!(rsp.matches("HTTP/1.1 200 Ok\r\n").count(), 2, "{:?}", rsp);
+    }
+
+    #[test]
+    fn second_head_end_split_after_the_first_request() {
+        let first = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let req = [&first[..], &first[..]].concat();
+        for carried in 1..=3 {
+            let rsp = serve_split(&req, &[req.len() - 4 + carried]);
+            let served = rsp.matches("HTTP/1.1 200 Ok\r\n").count();
+            assert_eq!(served, 2, "{}: {:?}", carried, rsp);
+        }
+    }
 }
@@ -1,11 +1,63 @@
 #[macro_use]
 extern crate log;
 
+mod accept;
+mod base64;
+#[cfg(feature = "compress")]
+mod compress;
+mod context;
+mod cookie;
+mod cors;
 mod date;
+mod etag;
 mod http_server;
+#[cfg(unix)]
+mod listener;
+mod method;
+mod metrics;
+mod mount;
+mod multipart;
+mod pool;
+mod proxy;
+mod query;
+mod range;
+mod rate_limit;
 mod request;
 mod response;
+mod routing;
+mod shutdown;
+mod static_files;
+pub mod test;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
+mod upgrade;
+mod version;
+mod vhost;
 
-pub use http_server::{HttpServer, HttpService, HttpServiceFactory};
+pub use context::ConnContext;
+pub use cookie::{CookieBuilder, Cookies, SameSite};
+pub use cors::Cors;
+pub use etag::{content_etag, file_etag};
+pub use http_server::{
+    AccessLog, DefaultStatus, HttpServer, HttpServerBuilder, HttpService, HttpServiceFactory,
+    Overload, Trace,
+};
+pub use method::Method;
+pub use metrics::Metrics;
+pub use mount::Mount;
+pub use multipart::{MultipartReader, Part};
+pub use query::{PathSegments, Query};
+pub use range::ByteRange;
+pub use rate_limit::RateLimit;
 pub use request::Request;
-pub use response::{BodyWriter, Response};
+#[cfg(feature = "compress")]
+pub use response::CompressWriter;
+pub use response::{BodyWriter, ChunkWriter, Redirect, Response, SseWriter};
+pub use routing::{Handler, Params, Router};
+pub use shutdown::Shutdown;
+pub use static_files::StaticFiles;
+pub use transport::{Listener, Transport};
+pub use upgrade::Upgraded;
+pub use version::Version;
+pub use vhost::VirtualHost;
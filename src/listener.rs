@@ -0,0 +1,93 @@
+//! creating the listening sockets with options that `TcpListener::bind` doesn't have
+
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Duration;
+
+use may::net::{TcpListener, TcpStream};
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+
+/// the backlog used by the standard library
+pub(crate) const DEFAULT_BACKLOG: u32 = 128;
+
+/// a socket for the address with `SO_REUSEADDR` set, like the standard library does
+fn socket(addr: SocketAddr) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    Ok(socket)
+}
+
+fn listen(socket: Socket, addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    socket.bind(&addr.into())?;
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+    Ok(unsafe { TcpListener::from_raw_fd(socket.into_raw_fd()) })
+}
+
+/// bind a listener with the given backlog
+pub(crate) fn bind(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    listen(socket(addr)?, addr, backlog)
+}
+
+/// bind a listener with `SO_REUSEPORT` so several of them can share the address
+///
+/// the kernel spreads the incoming connections over the listeners
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_reuseport(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = socket(addr)?;
+    socket.set_reuse_port(true)?;
+    listen(socket, addr, backlog)
+}
+
+/// bind a listener on the unspecified ipv6 address, with or without `IPV6_V6ONLY`
+///
+/// without it the listener also accepts the ipv4 connections as mapped addresses
+pub(crate) fn bind_ipv6(port: u16, only_v6: bool, backlog: u32) -> io::Result<TcpListener> {
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    let socket = socket(addr)?;
+    socket.set_only_v6(only_v6)?;
+    listen(socket, addr, backlog)
+}
+
+/// take over the listening socket at the fd, like one passed by systemd
+///
+/// the fd must be a tcp socket that's already listening, it's closed with
+/// the returned listener
+#[cfg(target_os = "linux")]
+pub(crate) fn from_fd(fd: RawFd) -> io::Result<TcpListener> {
+    let not_listener = || {
+        let msg = "the fd is not a listening tcp socket";
+        Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+    };
+    if fd < 0 {
+        return not_listener();
+    }
+    // only borrowed while it's checked, a wrong fd is left open
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&borrowed);
+    let domain = socket.domain()?;
+    if (domain != Domain::IPV4 && domain != Domain::IPV6)
+        || socket.r#type()? != Type::STREAM
+        || !socket.is_listener()?
+    {
+        return not_listener();
+    }
+    Ok(unsafe { TcpListener::from_raw_fd(fd) })
+}
+
+/// turn on `SO_KEEPALIVE` with the idle time before the first probe
+///
+/// the interval of the probes and how many are sent are left to the system,
+/// on linux that's 75 seconds and 9 probes
+pub(crate) fn set_keepalive(stream: &TcpStream, idle: Duration) -> io::Result<()> {
+    // the fd is borrowed for the call only, the stream still owns it
+    let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+    SockRef::from(&fd).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))
+}
+
+/// set or clear `TCP_CORK` on the stream
+#[cfg(target_os = "linux")]
+pub(crate) fn set_cork(stream: &TcpStream, cork: bool) -> io::Result<()> {
+    let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+    SockRef::from(&fd).set_cork(cork)
+}
@@ -0,0 +1,78 @@
+//! the counters of a server
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// the counters updated by the connections of a server
+///
+/// it's returned by `HttpServer::metrics`, the values only go up except
+/// `active_connections`, so a scraper can turn them into rates
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    active_connections: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+impl Metrics {
+    /// the responses sent, including the ones for rejected requests
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// the connections being served now
+    pub fn active_connections(&self) -> u64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// the bytes read by the request loop, the part of a body the service
+    /// reads from the socket itself is not counted
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// the bytes of the responses, heads included
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// the responses with a `4xx` status
+    pub fn client_errors(&self) -> u64 {
+        self.client_errors.load(Ordering::Relaxed)
+    }
+
+    /// the responses with a `5xx` status
+    pub fn server_errors(&self) -> u64 {
+        self.server_errors.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn read(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn response(&self, code: &str, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        match code.as_bytes().first() {
+            Some(b'4') => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            Some(b'5') => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    /// count the connection as active until the returned guard is dropped
+    pub(crate) fn connection(&self) -> ActiveConnection<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection(self)
+    }
+}
+
+pub(crate) struct ActiveConnection<'a>(&'a Metrics);
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
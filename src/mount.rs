@@ -0,0 +1,186 @@
+//! composing services under path prefixes
+
+use std::io;
+
+use crate::http_server::HttpService;
+use crate::request::Request;
+use crate::response::Response;
+
+/// a service that the `Mount` can clone for each connection
+pub(crate) trait MountedService: HttpService + Send + Sync {
+    fn clone_box(&self) -> Box<dyn MountedService>;
+}
+
+impl<T: HttpService + Clone + Send + Sync + 'static> MountedService for T {
+    fn clone_box(&self) -> Box<dyn MountedService> {
+        Box::new(self.clone())
+    }
+}
+
+/// a service that passes each request to the service mounted at the longest
+/// matching path prefix
+///
+/// a prefix matches whole segments, so `/api` matches `/api` and `/api/keys`
+/// but not `/apikeys`. the inner service sees the `Request::path` without
+/// the prefix. a request matching no prefix is answered with `404 Not Found`
+///
+/// ```no_run
+/// use may_minihttp::{HttpServer, Mount, Router, StaticFiles};
+///
+/// let api = Router::new().get("/users", |_, rsp| {
+///     rsp.text("[]");
+///     Ok(())
+/// });
+/// let app = Mount::new()
+///     .mount("/api", api)
+///     .mount("/static", StaticFiles::new("./public"));
+/// HttpServer(app).start("127.0.0.1:8080").unwrap();
+/// ```
+#[derive(Default)]
+pub struct Mount {
+    mounts: Vec<(String, Box<dyn MountedService>)>,
+    /// the service of the current request, for `on_error`
+    current: Option<usize>,
+}
+
+impl Clone for Mount {
+    fn clone(&self) -> Self {
+        Mount {
+            mounts: self
+                .mounts
+                .iter()
+                .map(|(prefix, service)| (prefix.clone(), service.clone_box()))
+                .collect(),
+            current: None,
+        }
+    }
+}
+
+impl Mount {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// mount the service at the prefix, a `/` prefix takes every request
+    pub fn mount<S>(mut self, prefix: &str, service: S) -> Self
+    where
+        S: HttpService + Clone + Send + Sync + 'static,
+    {
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        let prefix = if prefix == "/" { String::new() } else { prefix };
+        self.mounts.push((prefix, Box::new(service)));
+        self
+    }
+
+    /// the mount with the longest prefix matching the path
+    fn find(&self, path: &str) -> Option<usize> {
+        self.mounts
+            .iter()
+            .enumerate()
+            .filter(|(_, (prefix, _))| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+            })
+            .max_by_key(|(_, (prefix, _))| prefix.len())
+            .map(|(i, _)| i)
+    }
+}
+
+impl HttpService for Mount {
+    fn call(&mut self, mut req: Request, rsp: &mut Response) -> io::Result<()> {
+        self.current = self.find(req.path());
+        match self.current {
+            Some(i) => {
+                let (prefix, service) = &mut self.mounts[i];
+                req.strip_prefix(prefix.len());
+                service.call(req, rsp)
+            }
+            None => {
+                rsp.status_code("404", "Not Found");
+                Ok(())
+            }
+        }
+    }
+
+    fn on_error(&mut self, e: &io::Error, rsp: &mut Response) {
+        match self.current {
+            Some(i) => self.mounts[i].1.on_error(e, rsp),
+            None => {
+                rsp.status_code("500", "Internal Server Error");
+                rsp.body_mut().extend_from_slice(e.to_string().as_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::Router;
+    use crate::test;
+
+    /// answers with its name and the path and query it sees
+    #[derive(Clone)]
+    struct Named(&'static str);
+
+    impl HttpService for Named {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            let query: Vec<_> = req.query().map(|(k, v)| format!("{}={}", k, v)).collect();
+            let body = format!("{} {} {}", self.0, req.path(), query.join("&"));
+            rsp.body_vec(body.into_bytes());
+            Ok(())
+        }
+    }
+
+    fn app() -> Mount {
+        Mount::new()
+            .mount("/api/", Named("api"))
+            .mount("/api/v2", Named("v2"))
+            .mount("static", Named("static"))
+    }
+
+    fn get(app: Mount, path: &str) -> String {
+        let req = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path);
+        let rsp = String::from_utf8(test::serve(app, req.as_bytes())).unwrap();
+        rsp.split_once("\r\n\r\n").unwrap().1.to_owned()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        assert_eq!(get(app(), "/api/keys"), "api /keys ");
+        assert_eq!(get(app(), "/api/v2/keys?a=1"), "v2 /keys?a=1 a=1");
+        assert_eq!(get(app(), "/api/v21"), "api /v21 ");
+        assert_eq!(get(app(), "/static/a.css"), "static /a.css ");
+    }
+
+    #[test]
+    fn bare_prefix_is_the_root() {
+        assert_eq!(get(app(), "/api"), "api / ");
+        // the query string is only left in `query`
+        assert_eq!(get(app(), "/api?a=1"), "api / a=1");
+    }
+
+    #[test]
+    fn prefix_matches_whole_segments() {
+        let req = b"GET /apikeys HTTP/1.1\r\nHost: a\r\n\r\n";
+        let rsp = test::serve(app(), req);
+        assert!(rsp.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn root_mount_takes_the_rest() {
+        let app = app().mount("/", Named("root"));
+        assert_eq!(get(app.clone(), "/apikeys"), "root /apikeys ");
+        assert_eq!(get(app, "/api/keys"), "api /keys ");
+    }
+
+    #[test]
+    fn router_sees_the_stripped_path() {
+        let users = Router::new().get("/users/:id", |req, rsp| {
+            rsp.body_vec(format!("user {:?}", req.param("id")).into_bytes());
+            Ok(())
+        });
+        let app = Mount::new().mount("/api", users);
+        assert_eq!(get(app, "/api/users/7"), "user Some(\"7\")");
+    }
+}
@@ -0,0 +1,356 @@
+//! streaming `multipart/form-data` bodies
+
+use std::io::{self, Read};
+
+use memchr::memmem;
+
+use crate::request::trim;
+
+/// how much of the body is read into the buffer at a time
+const READ_SIZE: usize = 8192;
+/// the longest header section of a part that is accepted
+const MAX_PART_HEAD: usize = 8192;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// reading the data of a part, or the preamble before the first one
+    Data,
+    /// the buffer starts with a delimiter
+    Delimiter,
+    Done,
+}
+
+/// the reader of the parts of a `multipart/form-data` body
+///
+/// it's returned by `Request::multipart`. only a small buffer of the body is
+/// kept, the data of each part is streamed from the connection when it's read
+///
+/// ```no_run
+/// # fn f(mut req: may_minihttp::Request) -> std::io::Result<()> {
+/// let mut parts = req.multipart()?;
+/// while let Some(mut part) = parts.next_part()? {
+///     if part.filename().is_some() {
+///         std::io::copy(&mut part, &mut std::io::sink())?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct MultipartReader<R> {
+    body: R,
+    /// `\r\n--` followed by the boundary
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    state: State,
+}
+
+fn unexpected_end() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "multipart body ended without the closing boundary",
+    )
+}
+
+fn invalid_part(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: Read> MultipartReader<R> {
+    pub(crate) fn new(body: R, boundary: &[u8]) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary);
+        MultipartReader {
+            body,
+            delimiter,
+            // the line end before the first delimiter is optional, pretend it's there
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            state: State::Data,
+        }
+    }
+
+    /// read more of the body into the buffer, `false` at the end of the body
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+        let res = self.body.read(&mut self.buf[len..]);
+        let n = *res.as_ref().unwrap_or(&0);
+        self.buf.truncate(len + n);
+        Ok(res? > 0)
+    }
+
+    /// read the data of the current part, `Ok(0)` at the delimiter after it
+    fn read_data(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.state != State::Data {
+                return Ok(0);
+            }
+            let data = &self.buf[self.pos..];
+            let n = match memmem::find(data, &self.delimiter) {
+                Some(0) => {
+                    self.state = State::Delimiter;
+                    return Ok(0);
+                }
+                Some(i) => i,
+                // the end of the buffer may be the start of the delimiter
+                None => data.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if n > 0 {
+                let n = n.min(out.len());
+                out[..n].copy_from_slice(&data[..n]);
+                self.pos += n;
+                return Ok(n);
+            }
+            if !self.fill()? {
+                return Err(unexpected_end());
+            }
+        }
+    }
+
+    /// the next part of the body, `None` after the closing boundary
+    ///
+    /// what's left of the data of the previous part is skipped
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        let mut skip = [0; 512];
+        while self.read_data(&mut skip)? > 0 {}
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        self.pos += self.delimiter.len();
+        // the delimiter is followed by `--` for the last one,
+        // or by a line end and the headers of the part
+        let head_len = loop {
+            let rest = &self.buf[self.pos..];
+            if rest.len() >= 2 {
+                if rest.starts_with(b"--") {
+                    self.state = State::Done;
+                    // the epilogue is ignored, but read so the request body is done
+                    io::copy(&mut self.body, &mut io::sink())?;
+                    return Ok(None);
+                }
+                if let Some(i) = memmem::find(rest, b"\r\n\r\n") {
+                    break i;
+                }
+                if rest.len() > MAX_PART_HEAD {
+                    return Err(invalid_part("multipart part headers too large"));
+                }
+            }
+            if !self.fill()? {
+                return Err(unexpected_end());
+            }
+        };
+
+        let head = String::from_utf8_lossy(&self.buf[self.pos..self.pos + head_len]);
+        let mut lines = head.split("\r\n");
+        // only some padding is allowed after the delimiter
+        if !trim(lines.next().unwrap_or_default().as_bytes()).is_empty() {
+            return Err(invalid_part("invalid multipart delimiter"));
+        }
+        let mut headers = Vec::new();
+        for line in lines {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_part("invalid multipart part header"))?;
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+        self.pos += head_len + 4;
+        self.state = State::Data;
+
+        let disposition = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("content-disposition"))
+            .map(|(_, v)| v.as_str())
+            .unwrap_or_default();
+        let name = disposition_param(disposition, "name");
+        let filename = disposition_param(disposition, "filename");
+        Ok(Some(Part {
+            reader: self,
+            headers,
+            name,
+            filename,
+        }))
+    }
+}
+
+/// the value of a parameter of `Content-Disposition`, unquoted
+fn disposition_param(value: &str, param: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|p| {
+        let (k, v) = p.split_once('=')?;
+        if !k.trim().eq_ignore_ascii_case(param) {
+            return None;
+        }
+        let v = v.trim();
+        match v.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            Some(v) => Some(v.replace("\\\"", "\"")),
+            None => Some(v.to_owned()),
+        }
+    })
+}
+
+/// a part of a multipart body, reading it reads the data of the part
+pub struct Part<'m, R> {
+    reader: &'m mut MultipartReader<R>,
+    headers: Vec<(String, String)>,
+    name: Option<String>,
+    filename: Option<String>,
+}
+
+impl<'m, R: Read> Part<'m, R> {
+    /// the headers of the part, in the order they are sent
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// the value of the first header with the given name, compared case insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    /// the form field name from `Content-Disposition`
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// the file name from `Content-Disposition`, only sent for an uploaded file
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+    }
+}
+
+impl<'m, R: Read> Read for Part<'m, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read_data(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test;
+    use crate::{HttpService, Request, Response};
+
+    const BODY: &[u8] = b"preamble\r\n--xyz\r\n\
+        Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+        value\r\n--xyz\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line\r\n--xy not the end\r\n--xyz--\r\nepilogue";
+
+    /// a body that hands out a few bytes per read, to split the delimiters
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(self.1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    /// the name, file name and data of a part
+    type Fields = (Option<String>, Option<String>, String);
+
+    fn parts<R: Read>(body: R) -> io::Result<Vec<Fields>> {
+        let mut reader = MultipartReader::new(body, b"xyz");
+        let mut parts = Vec::new();
+        while let Some(mut part) = reader.next_part()? {
+            let mut data = String::new();
+            part.read_to_string(&mut data)?;
+            let name = part.name().map(str::to_owned);
+            parts.push((name, part.filename().map(str::to_owned), data));
+        }
+        Ok(parts)
+    }
+
+    #[test]
+    fn parts_are_read() {
+        let expected = [
+            (Some("field".to_owned()), None, "value".to_owned()),
+            (
+                Some("file".to_owned()),
+                Some("a \"b\".txt".to_owned()),
+                "line\r\n--xy not the end".to_owned(),
+            ),
+        ];
+        assert_eq!(parts(BODY).unwrap(), expected);
+        for n in 1..8 {
+            assert_eq!(parts(Trickle(BODY, n)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn unread_data_is_skipped() {
+        let mut reader = MultipartReader::new(BODY, b"xyz");
+        reader.next_part().unwrap().unwrap();
+        let part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.content_type(), Some("text/plain"));
+        assert!(reader.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_closing_boundary_is_an_error() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue";
+        let err = parts(&body[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    struct Names;
+
+    impl HttpService for Names {
+        fn call(&mut self, mut req: Request, rsp: &mut Response) -> io::Result<()> {
+            let mut names = Vec::new();
+            let mut parts = req.multipart()?;
+            while let Some(part) = parts.next_part()? {
+                names.push(part.name().unwrap_or_default().to_owned());
+            }
+            rsp.body_vec(names.join(",").into_bytes());
+            Ok(())
+        }
+    }
+
+    fn request(content_type: &str) -> Vec<u8> {
+        let mut req = format!(
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            BODY.len()
+        )
+        .into_bytes();
+        req.extend_from_slice(BODY);
+        req
+    }
+
+    #[test]
+    fn request_body_is_read() {
+        let rsp = test::serve(Names, &request("multipart/form-data; boundary=\"xyz\""));
+        assert!(
+            rsp.ends_with(b"\r\n\r\nfield,file"),
+            "{}",
+            String::from_utf8_lossy(&rsp)
+        );
+    }
+
+    #[test]
+    fn other_content_type_is_415() {
+        let rsp = test::serve(Names, &request("text/plain"));
+        assert!(rsp.starts_with(b"HTTP/1.1 415 Unsupported Media Type\r\n"));
+    }
+
+    #[test]
+    fn missing_boundary_is_400() {
+        let rsp = test::serve(Names, &request("multipart/form-data"));
+        assert!(rsp.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
@@ -0,0 +1,90 @@
+//! reusing the connection buffers across connections
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use bytes::BytesMut;
+
+/// the request, response and body buffers of a connection
+pub(crate) struct Buffers {
+    pub(crate) req: BytesMut,
+    pub(crate) rsp: BytesMut,
+    pub(crate) body: BytesMut,
+}
+
+/// a bounded free list of connection buffers
+///
+/// a buffer that grew larger than `max_capacity` is dropped instead of kept,
+/// so a few large requests don't pin their memory in the pool
+pub(crate) struct BufferPool {
+    free: Mutex<Vec<Buffers>>,
+    max_len: usize,
+    max_capacity: usize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(max_len: usize, max_capacity: usize) -> Self {
+        BufferPool {
+            free: Mutex::new(Vec::with_capacity(max_len)),
+            max_len,
+            max_capacity,
+        }
+    }
+
+    fn take(&self) -> Option<Buffers> {
+        self.free.lock().unwrap().pop()
+    }
+
+    fn put(&self, mut bufs: Buffers) {
+        let kept = [&bufs.req, &bufs.rsp, &bufs.body];
+        if kept.iter().any(|b| b.capacity() > self.max_capacity) {
+            return;
+        }
+        bufs.req.clear();
+        bufs.rsp.clear();
+        bufs.body.clear();
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_len {
+            free.push(bufs);
+        }
+    }
+}
+
+/// the buffers of a connection, given back to the pool when it ends
+pub(crate) struct PooledBuffers<'a> {
+    pool: Option<&'a BufferPool>,
+    bufs: Option<Buffers>,
+}
+
+impl<'a> PooledBuffers<'a> {
+    /// take buffers from the pool, `new` makes them when it's empty or there's none
+    pub(crate) fn get(pool: Option<&'a BufferPool>, new: impl FnOnce() -> Buffers) -> Self {
+        let bufs = pool.and_then(|p| p.take()).unwrap_or_else(new);
+        PooledBuffers {
+            pool,
+            bufs: Some(bufs),
+        }
+    }
+}
+
+impl Deref for PooledBuffers<'_> {
+    type Target = Buffers;
+
+    fn deref(&self) -> &Buffers {
+        self.bufs.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledBuffers<'_> {
+    fn deref_mut(&mut self) -> &mut Buffers {
+        self.bufs.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBuffers<'_> {
+    fn drop(&mut self) {
+        if let (Some(pool), Some(bufs)) = (self.pool, self.bufs.take()) {
+            pool.put(bufs);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! the PROXY protocol header sent by a load balancer in front of the server
+
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// the start of a v2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// the longest v1 header, with the `\r\n`
+const V1_MAX_LEN: usize = 107;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// read the v1 or v2 PROXY header at the start of the connection
+///
+/// return the source address of the client, `None` for a connection the
+/// load balancer opened itself, like a health check. only the header is
+/// read so the request after it is left in the stream
+pub(crate) fn read_header<R: Read>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    // the shortest v1 header is longer than the v2 signature
+    let mut start = [0; 12];
+    stream.read_exact(&mut start)?;
+    if &start == V2_SIGNATURE {
+        read_v2(stream)
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start)
+    } else {
+        Err(invalid("missing PROXY header"))
+    }
+}
+
+fn read_v1<R: Read>(stream: &mut R, start: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    // a byte at a time, the header has no length
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY header too long"));
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    parse_v1(&line[..line.len() - 2])
+}
+
+/// parse `PROXY TCP4 <src> <dst> <src port> <dst port>` without the `\r\n`
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let err = || invalid("invalid PROXY header");
+    let line = std::str::from_utf8(line).map_err(|_| err())?;
+    let mut fields = line.split(' ').skip(1);
+    let v4 = match fields.next() {
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        // the rest of the line is ignored
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(err()),
+    };
+    let fields: Vec<&str> = fields.collect();
+    let [src, dst, src_port, dst_port] = fields[..] else {
+        return Err(err());
+    };
+    let addr = |s: &str| {
+        s.parse::<IpAddr>()
+            .ok()
+            .filter(|ip| ip.is_ipv4() == v4)
+            .ok_or_else(err)
+    };
+    let port = |s: &str| {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+        s.parse::<u16>().map_err(|_| err())
+    };
+    let src = addr(src)?;
+    addr(dst)?;
+    let src_port = port(src_port)?;
+    port(dst_port)?;
+    Ok(Some(SocketAddr::new(src, src_port)))
+}
+
+fn read_v2<R: Read>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0; 4];
+    stream.read_exact(&mut head)?;
+    let [ver_cmd, family, len_hi, len_lo] = head;
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY version"));
+    }
+    let mut addrs = vec![0; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    stream.read_exact(&mut addrs)?;
+
+    match ver_cmd & 0x0f {
+        // LOCAL
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(invalid("invalid PROXY command")),
+    }
+    let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+    match family >> 4 {
+        // AF_INET, the source and destination addresses and ports
+        1 if addrs.len() >= 12 => {
+            let ip: [u8; 4] = addrs[..4].try_into().unwrap();
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port(8))))
+        }
+        // AF_INET6
+        2 if addrs.len() >= 36 => {
+            let ip: [u8; 16] = addrs[..16].try_into().unwrap();
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port(32))))
+        }
+        // AF_UNSPEC or AF_UNIX, there's no ip address
+        0 | 3 => Ok(None),
+        _ => Err(invalid("invalid PROXY address")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(header: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let mut data = header.to_vec();
+        data.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        let mut stream = io::Cursor::new(data);
+        let source = read_header(&mut stream)?;
+        // the request is left in the stream
+        assert_eq!(
+            &stream.get_ref()[stream.position() as usize..],
+            b"GET / HTTP/1.1\r\n"
+        );
+        Ok(source)
+    }
+
+    fn addr(s: &str) -> Option<SocketAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn v1_addresses() {
+        let header = b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\n";
+        assert_eq!(read(header).unwrap(), addr("192.168.0.1:56324"));
+        let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        assert_eq!(read(header).unwrap(), addr("[2001:db8::1]:4000"));
+        let header = b"PROXY UNKNOWN ffff:f...f:ffff 0 0\r\n";
+        assert_eq!(read(header).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_v1_headers() {
+        for header in [
+            &b"PROXY TCP4 192.168.0.1 10.0.0.1 56324\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 10.0.0.1 56324 443\r\n",
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 +5632 443\r\n",
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 65536 443\r\n",
+            b"PROXY UDP4 192.168.0.1 10.0.0.1 56324 443\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\n\r\n",
+        ] {
+            let err = read(header).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.resize(200, b'a');
+        assert_eq!(read(&long).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn v2(ver_cmd: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[ver_cmd, family]);
+        header.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        header.extend_from_slice(addrs);
+        header
+    }
+
+    #[test]
+    fn v2_addresses() {
+        let ipv4 = [192, 168, 0, 1, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb];
+        assert_eq!(
+            read(&v2(0x21, 0x11, &ipv4)).unwrap(),
+            addr("192.168.0.1:56324")
+        );
+
+        let mut ipv6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        ipv6.extend_from_slice(&[0; 16]);
+        ipv6.extend_from_slice(&[0x0f, 0xa0, 0, 80]);
+        // the tlvs after the addresses are skipped
+        ipv6.extend_from_slice(&[0x04, 0, 1, 0]);
+        assert_eq!(
+            read(&v2(0x21, 0x21, &ipv6)).unwrap(),
+            addr("[2001:db8::1]:4000")
+        );
+    }
+
+    #[test]
+    fn v2_without_a_client_address() {
+        // LOCAL, like a health check of the load balancer
+        assert_eq!(read(&v2(0x20, 0x11, &[0; 12])).unwrap(), None);
+        // AF_UNSPEC
+        assert_eq!(read(&v2(0x21, 0x00, &[])).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_v2_headers() {
+        for header in [
+            v2(0x11, 0x11, &[0; 12]),
+            v2(0x22, 0x11, &[0; 12]),
+            v2(0x21, 0x11, &[0; 8]),
+            v2(0x21, 0x41, &[0; 12]),
+        ] {
+            let err = read(&header).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        let mut cut = v2(0x21, 0x11, &[0; 12]);
+        cut.truncate(20);
+        let err = read_header(&mut &cut[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
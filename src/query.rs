@@ -0,0 +1,191 @@
+//! query string and path parsing with percent decoding
+
+use std::borrow::Cow;
+
+/// the iterator over the `key=value` pairs of a query string
+///
+/// it's returned by `Request::query`, the keys and values are percent
+/// decoded and a `+` is decoded as a space
+#[derive(Clone)]
+pub struct Query<'a> {
+    pairs: std::str::Split<'a, char>,
+}
+
+impl<'a> Query<'a> {
+    pub(crate) fn new(query: &'a str) -> Self {
+        Query {
+            pairs: query.split('&'),
+        }
+    }
+}
+
+impl<'a> Iterator for Query<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pair = self.pairs.next()?;
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            return Some((percent_decode(key, true), percent_decode(value, true)));
+        }
+    }
+}
+
+/// the iterator over the percent decoded segments of a request path
+///
+/// it's returned by `Request::path_segments`
+pub struct PathSegments<'a> {
+    segments: std::vec::IntoIter<Cow<'a, str>>,
+}
+
+impl<'a> PathSegments<'a> {
+    /// split the path on `/` and normalize it
+    ///
+    /// empty and `.` segments are skipped, a `..` removes the segment before
+    /// it and never goes above the root. the segments are checked after the
+    /// decoding so an encoded `%2e%2e` is a `..` as well
+    pub(crate) fn new(path: &'a str) -> Self {
+        let mut segments = Vec::new();
+        for segment in path.split('/') {
+            let segment = percent_decode(segment, false);
+            match &*segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                _ => segments.push(segment),
+            }
+        }
+        PathSegments {
+            segments: segments.into_iter(),
+        }
+    }
+}
+
+impl<'a> Iterator for PathSegments<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.segments.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.segments.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for PathSegments<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.segments.next_back()
+    }
+}
+
+impl<'a> ExactSizeIterator for PathSegments<'a> {}
+
+/// parse an `application/x-www-form-urlencoded` body
+///
+/// `None` if the body is not utf8 or has an invalid `%` escape
+pub(crate) fn parse_form(body: &[u8]) -> Option<Vec<(String, String)>> {
+    let body = std::str::from_utf8(body).ok()?;
+    let bytes = body.as_bytes();
+    let valid = bytes.iter().enumerate().all(|(i, &b)| {
+        b != b'%'
+            || (bytes.get(i + 1).and_then(hex).is_some()
+                && bytes.get(i + 2).and_then(hex).is_some())
+    });
+    if !valid {
+        return None;
+    }
+    Some(
+        Query::new(body)
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect(),
+    )
+}
+
+fn hex(b: &u8) -> Option<u8> {
+    (*b as char).to_digit(16).map(|d| d as u8)
+}
+
+/// decode the `%XX` escapes, an invalid escape is kept as it is
+///
+/// invalid utf8 in the decoded bytes is replaced with `U+FFFD`
+pub(crate) fn percent_decode(s: &str, plus_as_space: bool) -> Cow<'_, str> {
+    let bytes = s.as_bytes();
+    if !bytes
+        .iter()
+        .any(|&b| b == b'%' || (plus_as_space && b == b'+'))
+    {
+        return Cow::Borrowed(s);
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => out.push(b' '),
+            b'%' => match (
+                bytes.get(i + 1).and_then(hex),
+                bytes.get(i + 2).and_then(hex),
+            ) {
+                (Some(h), Some(l)) => {
+                    out.push(h << 4 | l);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    match String::from_utf8(out) {
+        Ok(s) => Cow::Owned(s),
+        Err(e) => Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(path: &str) -> Vec<Cow<'_, str>> {
+        PathSegments::new(path).collect()
+    }
+
+    #[test]
+    fn dot_segments_are_resolved() {
+        assert_eq!(segments("/a/./b//c/"), ["a", "b", "c"]);
+        assert_eq!(segments("/a/b/../c"), ["a", "c"]);
+        assert_eq!(segments("/../../etc/passwd"), ["etc", "passwd"]);
+    }
+
+    #[test]
+    fn encoded_dot_segments_are_resolved() {
+        assert_eq!(segments("/a/%2e%2e/b"), ["b"]);
+        assert_eq!(segments("/%2E%2e/%2e/etc/passwd"), ["etc", "passwd"]);
+        assert_eq!(segments("/a/.%2e/.%2E/b"), ["b"]);
+    }
+
+    #[test]
+    fn encoded_slash_stays_in_the_segment() {
+        assert_eq!(segments("/a%2Fb/c"), ["a/b", "c"]);
+        // a `..` made of encoded slashes is not a dot segment
+        assert_eq!(segments("/..%2f..%2fetc"), ["../../etc"]);
+        assert_eq!(segments("/a%5c..%5cb"), ["a\\..\\b"]);
+    }
+
+    #[test]
+    fn drive_prefix_is_a_plain_segment() {
+        assert_eq!(segments("/C:/Windows"), ["C:", "Windows"]);
+        assert_eq!(segments("/C%3a/x"), ["C:", "x"]);
+    }
+
+    #[test]
+    fn invalid_escape_is_kept() {
+        assert_eq!(segments("/100%/%zz/%4"), ["100%", "%zz", "%4"]);
+        assert_eq!(segments("/caf%C3%A9"), ["café"]);
+    }
+}
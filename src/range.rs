@@ -0,0 +1,115 @@
+//! byte range requests
+
+use std::ops::Range;
+
+/// the part of the content asked for by the `Range` header
+///
+/// it's returned by `Request::byte_range` for a content of a known length
+/// and passed to `Response::content_range` to send the part
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// no range or one that is ignored, the whole content is sent
+    Full,
+    /// the bytes of the content to send with `206 Partial Content`
+    Partial(Range<u64>),
+    /// a range out of the content or several ranges, answered with
+    /// `416 Range Not Satisfiable`
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// parse a `Range` header value for a content of `len` bytes
+    ///
+    /// a value that is not a `bytes` range is ignored like the rfc tells
+    pub(crate) fn parse(value: &[u8], len: u64) -> Self {
+        let value = match std::str::from_utf8(value) {
+            Ok(v) => v.trim(),
+            Err(_) => return ByteRange::Full,
+        };
+        let spec = match value.split_once('=') {
+            Some((unit, spec)) if unit.trim().eq_ignore_ascii_case("bytes") => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+        // only a single range is supported
+        if spec.contains(',') {
+            return ByteRange::Unsatisfiable;
+        }
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return ByteRange::Full,
+        };
+        let num = |s: &str| {
+            if s.bytes().all(|b| b.is_ascii_digit()) {
+                s.parse::<u64>().ok()
+            } else {
+                None
+            }
+        };
+
+        if start.is_empty() {
+            // the last `end` bytes
+            return match num(end) {
+                Some(0) => ByteRange::Unsatisfiable,
+                Some(_) if len == 0 => ByteRange::Unsatisfiable,
+                Some(n) => ByteRange::Partial(len.saturating_sub(n)..len),
+                None => ByteRange::Full,
+            };
+        }
+        let start = match num(start) {
+            Some(start) => start,
+            None => return ByteRange::Full,
+        };
+        let end = if end.is_empty() {
+            len
+        } else {
+            match num(end) {
+                Some(end) if end >= start => end.saturating_add(1).min(len),
+                _ => return ByteRange::Full,
+            }
+        };
+        if start >= len {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Partial(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> ByteRange {
+        ByteRange::parse(value.as_bytes(), 100)
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse("bytes=0-9"), ByteRange::Partial(0..10));
+        assert_eq!(parse("bytes = 90-"), ByteRange::Partial(90..100));
+        assert_eq!(parse("bytes=95-200"), ByteRange::Partial(95..100));
+        assert_eq!(parse("bytes=-10"), ByteRange::Partial(90..100));
+        assert_eq!(parse("bytes=-500"), ByteRange::Partial(0..100));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-1,5-6"), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse(b"bytes=-1", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn invalid_ranges_are_ignored() {
+        for value in [
+            "items=0-1",
+            "bytes=5-1",
+            "bytes=a-",
+            "bytes=+1-2",
+            "bytes=1",
+        ] {
+            assert_eq!(parse(value), ByteRange::Full, "{}", value);
+        }
+        assert_eq!(ByteRange::parse(b"bytes=\xff-1", 100), ByteRange::Full);
+    }
+}
@@ -0,0 +1,311 @@
+//! limiting the request rate of each client
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http_server::HttpService;
+use crate::request::Request;
+use crate::response::Response;
+
+/// the buckets are split over the shards so the connections rarely wait on each other
+const SHARDS: usize = 16;
+
+type KeyFn = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    Custom(String),
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+struct Limiter {
+    /// the tokens added each second
+    rate: f64,
+    burst: f64,
+    max_clients: usize,
+    key_fn: Option<Box<KeyFn>>,
+    hasher: RandomState,
+    shards: Vec<Mutex<HashMap<Key, Bucket>>>,
+}
+
+impl Limiter {
+    /// take a token of the client, or return how long until there's one
+    // `BuildHasher::hash_one` is newer than the rust the crate supports
+    #[allow(clippy::manual_hash_one)]
+    fn acquire(&self, key: Key) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        let shard = hasher.finish() as usize % SHARDS;
+        let mut buckets = self.shards[shard].lock().unwrap();
+
+        let max_len = (self.max_clients / SHARDS).max(1);
+        if buckets.len() >= max_len && !buckets.contains_key(&key) {
+            self.evict(&mut buckets, now);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            last: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    /// drop the buckets that are full again, they are the same as new ones,
+    /// or the least recently used one if all the clients are active
+    fn evict(&self, buckets: &mut HashMap<Key, Bucket>, now: Instant) {
+        buckets.retain(|_, b| self.refill(b, now) < self.burst);
+        let max_len = (self.max_clients / SHARDS).max(1);
+        if buckets.len() >= max_len {
+            let oldest = buckets
+                .iter()
+                .min_by_key(|(_, b)| b.last)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                buckets.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// a service that limits the request rate of each client with a token bucket
+///
+/// a client gets `burst` requests at once and then `requests` each `per`,
+/// going over it is answered with `429 Too Many Requests` and a `Retry-After`.
+/// the clients are told apart by their ip address unless a `key_fn` is set.
+/// the buckets are shared by all the connections of the server, at most
+/// `max_clients` of them are kept
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use may_minihttp::{HttpServer, RateLimit, StaticFiles};
+///
+/// let limited = RateLimit::new(StaticFiles::new("./public"), 10, Duration::from_secs(1)).burst(20);
+/// HttpServer(limited).start("127.0.0.1:8080").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+}
+
+impl<S> RateLimit<S> {
+    /// allow `requests` for each client each `per`
+    pub fn new(inner: S, requests: u32, per: Duration) -> Self {
+        assert!(requests > 0 && !per.is_zero(), "invalid rate limit");
+        RateLimit {
+            inner,
+            limiter: Arc::new(Limiter {
+                rate: requests as f64 / per.as_secs_f64(),
+                burst: requests as f64,
+                max_clients: 10_000,
+                key_fn: None,
+                hasher: RandomState::new(),
+                shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            }),
+        }
+    }
+
+    fn limiter_mut(&mut self) -> &mut Limiter {
+        Arc::get_mut(&mut self.limiter).expect("rate limit configured after it's cloned")
+    }
+
+    /// the requests a client may send at once, default is the `requests` of `new`
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.limiter_mut().burst = burst.max(1) as f64;
+        self
+    }
+
+    /// the most clients tracked at once, default is 10000
+    ///
+    /// when there are more the idle clients are forgotten first
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.limiter_mut().max_clients = max_clients;
+        self
+    }
+
+    /// tell the clients apart by the key returned for the request, like an api key header
+    ///
+    /// a request the function returns `None` for is not limited
+    pub fn key_fn<F>(mut self, key_fn: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.limiter_mut().key_fn = Some(Box::new(key_fn));
+        self
+    }
+}
+
+impl<S: HttpService> HttpService for RateLimit<S> {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+        let key = match &self.limiter.key_fn {
+            Some(key_fn) => key_fn(&req).map(Key::Custom),
+            None => req.peer_addr().map(|addr| Key::Ip(addr.ip())),
+        };
+        if let Some(key) = key {
+            if let Err(wait) = self.limiter.acquire(key) {
+                rsp.status_code("429", "Too Many Requests");
+                let secs = wait.as_secs() + (wait.subsec_nanos() > 0) as u64;
+                rsp.add_header("Retry-After", &secs.max(1).to_string());
+                return Ok(());
+            }
+        }
+        self.inner.call(req, rsp)
+    }
+
+    fn on_error(&mut self, e: &io::Error, rsp: &mut Response) {
+        self.inner.on_error(e, rsp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test;
+
+    #[derive(Clone)]
+    struct Hello;
+
+    impl HttpService for Hello {
+        fn call(&mut self, _req: Request, rsp: &mut Response) -> io::Result<()> {
+            rsp.body("hello");
+            Ok(())
+        }
+    }
+
+    /// the clients are told apart by `X-Client`, a request without one is not limited
+    fn limited(requests: u32, per: Duration) -> RateLimit<Hello> {
+        RateLimit::new(Hello, requests, per).key_fn(|req| {
+            let client = req.header("x-client")?;
+            Some(String::from_utf8_lossy(client).into_owned())
+        })
+    }
+
+    fn statuses(rsp: &[u8]) -> Vec<String> {
+        String::from_utf8_lossy(rsp)
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|r| r[..3].to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn over_the_rate_is_429() {
+        let a = "GET / HTTP/1.1\r\nHost: a\r\nX-Client: a\r\n\r\n";
+        let b = "GET / HTTP/1.1\r\nHost: a\r\nX-Client: b\r\n\r\n";
+        let anonymous = "GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let req = [a, a, a, b, anonymous, anonymous, anonymous].concat();
+        let rsp = test::serve(limited(2, Duration::from_secs(60)), req.as_bytes());
+        assert_eq!(
+            statuses(&rsp),
+            ["200", "200", "429", "200", "200", "200", "200"]
+        );
+        // a token each 30 seconds
+        let rsp = String::from_utf8(rsp).unwrap();
+        let limited = rsp.split("HTTP/1.1 ").nth(3).unwrap();
+        assert!(limited.starts_with("429 Too Many Requests\r\n"));
+        assert!(limited.contains("\r\nRetry-After: 30\r\n"));
+    }
+
+    #[test]
+    fn limit_is_shared_by_the_clones() {
+        let limited = limited(1, Duration::from_secs(60));
+        let req = b"GET / HTTP/1.1\r\nHost: a\r\nX-Client: a\r\n\r\n";
+        assert_eq!(statuses(&test::serve(limited.clone(), req)), ["200"]);
+        assert_eq!(statuses(&test::serve(limited, req)), ["429"]);
+    }
+
+    #[test]
+    fn burst_is_allowed_at_once() {
+        let limited = limited(1, Duration::from_secs(60)).burst(3);
+        let req = "GET / HTTP/1.1\r\nHost: a\r\nX-Client: a\r\n\r\n".repeat(4);
+        let rsp = test::serve(limited, req.as_bytes());
+        assert_eq!(statuses(&rsp), ["200", "200", "200", "429"]);
+    }
+
+    #[test]
+    fn clients_are_evicted() {
+        let limited = RateLimit::new(Hello, 1, Duration::from_secs(60)).max_clients(1);
+        let limiter = &limited.limiter;
+        for i in 0..100 {
+            assert!(limiter.acquire(Key::Custom(i.to_string())).is_ok());
+        }
+        // a bucket for each shard at most
+        let len: usize = limiter.shards.iter().map(|s| s.lock().unwrap().len()).sum();
+        assert!(len <= SHARDS, "{}", len);
+    }
+
+    #[test]
+    fn full_buckets_are_evicted_first() {
+        let limited = RateLimit::new(Hello, 1, Duration::from_secs(60)).max_clients(64);
+        let limiter = &limited.limiter;
+        let now = Instant::now();
+        let mut buckets = HashMap::new();
+        let long_ago = now - Duration::from_secs(120);
+        buckets.insert(
+            Key::Custom("idle".into()),
+            Bucket {
+                tokens: 0.0,
+                last: long_ago,
+            },
+        );
+        buckets.insert(
+            Key::Custom("a".into()),
+            Bucket {
+                tokens: 0.0,
+                last: now,
+            },
+        );
+        buckets.insert(
+            Key::Custom("b".into()),
+            Bucket {
+                tokens: 0.5,
+                last: now,
+            },
+        );
+        buckets.insert(
+            Key::Custom("c".into()),
+            Bucket {
+                tokens: 0.0,
+                last: now,
+            },
+        );
+        // 4 buckets for each shard, the idle one is the same as a new one
+        limiter.evict(&mut buckets, now);
+        assert_eq!(buckets.len(), 3);
+        assert!(!buckets.contains_key(&Key::Custom("idle".into())));
+        // all the clients are active, the least recently used one goes
+        buckets.get_mut(&Key::Custom("b".into())).unwrap().last = long_ago;
+        buckets.insert(
+            Key::Custom("d".into()),
+            Bucket {
+                tokens: 0.0,
+                last: now,
+            },
+        );
+        limiter.evict(&mut buckets, now);
+        assert_eq!(buckets.len(), 3);
+        assert!(!buckets.contains_key(&Key::Custom("b".into())));
+    }
+}
@@ -1,20 +1,73 @@
 use bytes::BytesMut;
 
-use std::io;
-use std::mem::MaybeUninit;
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::cookie::CookieBuilder;
+use crate::method::Method;
+use crate::range::ByteRange;
+#[cfg(target_os = "linux")]
+use crate::transport::sendfile_wait;
+use crate::transport::{flush_wait, write_wait, Conn};
+use crate::upgrade::{OnUpgrade, Upgraded};
+use crate::version::Version;
 
 pub struct Response<'a> {
     headers: [&'static str; 16],
     headers_len: usize,
+    /// the headers added by `add_header`, each line starts with CRLF
+    dyn_headers: String,
     status_message: StatusMessage,
+    /// the service set the status with `status_code`
+    status_set: bool,
     body: Body,
     rsp_buf: &'a mut BytesMut,
+    keep_alive: bool,
+    /// the version of the request, a HTTP/1.0 client is told the connection is kept
+    version: Version,
+    /// answering a HEAD request, only the head is sent
+    head: bool,
+    stream: Option<&'a Conn>,
+    /// the stream is corked while the response is written
+    corked: bool,
+    /// the `Server` header value, `None` to omit it
+    server: Option<&'a str>,
+    /// the bytes already sent by a `ChunkWriter`
+    sent: usize,
+    upgrade: Option<OnUpgrade>,
+    /// the `If-None-Match` of a GET or HEAD request
+    if_none_match: Option<String>,
+    /// the service opted in with `auto_etag`
+    auto_etag: bool,
+    /// the buffered body is sent chunked, see `set_chunked`
+    chunked: bool,
+    /// where each `body_mut` call of a chunked body started, the chunks end there
+    chunk_starts: Vec<usize>,
+    #[cfg(feature = "compress")]
+    compress: Compress,
+}
+
+/// the compression settings of a response
+#[cfg(feature = "compress")]
+struct Compress {
+    /// the service opted in with `auto_compress`
+    enabled: bool,
+    /// the best encoding the request accepts
+    encoding: Option<crate::compress::Encoding>,
+    min_size: usize,
 }
 
 enum Body {
     Str(&'static str),
     Vec(Vec<u8>),
     Dummy,
+    /// streamed by a `ChunkWriter`, the buffer holds the chunks not sent yet.
+    /// a body of a known length from `body_from_reader` is always finished
+    Chunked {
+        finished: bool,
+    },
 }
 
 struct StatusMessage {
@@ -23,36 +76,196 @@ struct StatusMessage {
 }
 
 impl<'a> Response<'a> {
-    pub(crate) fn new(rsp_buf: &'a mut BytesMut) -> Response {
-        let headers: [&'static str; 16] = unsafe {
-            let h: [MaybeUninit<&'static str>; 16] = MaybeUninit::uninit().assume_init();
-            std::mem::transmute(h)
-        };
-
+    pub(crate) fn new(rsp_buf: &'a mut BytesMut) -> Response<'a> {
         Response {
-            headers,
+            headers: [""; 16],
             headers_len: 0,
+            dyn_headers: String::new(),
             body: Body::Dummy,
             status_message: StatusMessage {
                 code: "200",
                 msg: "Ok",
             },
+            status_set: false,
             rsp_buf,
+            keep_alive: true,
+            version: Version::Http11,
+            head: false,
+            stream: None,
+            corked: false,
+            server: Some("may"),
+            sent: 0,
+            upgrade: None,
+            if_none_match: None,
+            auto_etag: false,
+            chunked: false,
+            chunk_starts: Vec::new(),
+            #[cfg(feature = "compress")]
+            compress: Compress {
+                enabled: false,
+                encoding: None,
+                min_size: crate::compress::DEFAULT_MIN_SIZE,
+            },
         }
     }
 
     pub fn status_code(&mut self, code: &'static str, msg: &'static str) -> &mut Self {
         self.status_message = StatusMessage { code, msg };
+        self.status_set = true;
         self
     }
 
+    /// add a header line like `"Content-Type: text/plain"`
+    ///
+    /// the first 16 are kept without copying, the ones after are appended
+    /// like the headers of `add_header`
     pub fn header(&mut self, header: &'static str) -> &mut Self {
-        debug_assert!(self.headers_len < 16);
-        *unsafe { self.headers.get_unchecked_mut(self.headers_len) } = header;
-        self.headers_len += 1;
+        match self.headers.get_mut(self.headers_len) {
+            Some(slot) => {
+                *slot = header;
+                self.headers_len += 1;
+            }
+            None => {
+                self.dyn_headers.push_str("\r\n");
+                self.dyn_headers.push_str(header);
+            }
+        }
+        self
+    }
+
+    /// add a header with a name and value that are not known at compile time
+    ///
+    /// the header is appended, so it can be repeated like `Set-Cookie`
+    ///
+    /// # Panics
+    ///
+    /// panics if the name is not a valid token or the value contains a CR, LF
+    /// or other control character, so that a header can't be injected
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        assert!(is_valid_name(name), "invalid header name: {:?}", name);
+        assert!(is_valid_value(value), "invalid header value: {:?}", value);
+        self.dyn_headers.push_str("\r\n");
+        self.dyn_headers.push_str(name);
+        self.dyn_headers.push_str(": ");
+        self.dyn_headers.push_str(value);
+        self
+    }
+
+    /// answer with `405 Method Not Allowed` and the methods in the `Allow` header
+    pub fn method_not_allowed(&mut self, allowed: &[Method]) -> &mut Self {
+        self.status_code("405", "Method Not Allowed");
+        self.allow(allowed)
+    }
+
+    /// add the `Allow` header with the methods, like for an `OPTIONS` request
+    pub fn allow(&mut self, methods: &[Method]) -> &mut Self {
+        let methods: Vec<_> = methods.iter().map(|m| m.as_str()).collect();
+        self.add_header("Allow", &methods.join(", "))
+    }
+
+    /// add a `Set-Cookie` header, each cookie is sent in its own header
+    pub fn set_cookie(&mut self, cookie: CookieBuilder) -> &mut Self {
+        // writing to a `String` never fails
+        write!(self.dyn_headers, "\r\nSet-Cookie: {}", cookie).unwrap();
         self
     }
 
+    /// set the status and `Content-Range` for the part of a content of `len` bytes
+    ///
+    /// a partial range is a `206 Partial Content` and an unsatisfiable one a
+    /// `416 Range Not Satisfiable`, nothing is set for the full content.
+    /// the body is still set by the service
+    pub fn content_range(&mut self, range: &ByteRange, len: u64) -> &mut Self {
+        match range {
+            ByteRange::Full => return self,
+            ByteRange::Partial(range) => {
+                self.status_code("206", "Partial Content");
+                write!(
+                    self.dyn_headers,
+                    "\r\nContent-Range: bytes {}-{}/{}",
+                    range.start,
+                    range.end - 1,
+                    len
+                )
+            }
+            ByteRange::Unsatisfiable => {
+                self.status_code("416", "Range Not Satisfiable");
+                write!(self.dyn_headers, "\r\nContent-Range: bytes */{}", len)
+            }
+        }
+        .unwrap();
+        self
+    }
+
+    /// set the `ETag` header, and answer `304 Not Modified` if the client has it
+    ///
+    /// return true when the response became a `304`, the service can skip
+    /// producing the body since it's not sent. see `file_etag` and
+    /// `content_etag` for making one
+    pub fn etag(&mut self, etag: &str) -> bool {
+        self.add_header("ETag", etag);
+        let not_modified = self
+            .if_none_match
+            .as_deref()
+            .is_some_and(|v| crate::etag::none_match(v, etag));
+        if not_modified {
+            self.status_code("304", "Not Modified");
+        }
+        not_modified
+    }
+
+    /// set a weak `ETag` from a hash of the body when a `200` response is sent
+    ///
+    /// it's like calling `etag` with `content_etag` of the body once it's
+    /// complete, a streamed body doesn't get one
+    pub fn auto_etag(&mut self) -> &mut Self {
+        self.auto_etag = true;
+        self
+    }
+
+    /// redirect the client to the location, with a small html body linking to it
+    ///
+    /// panic if the location has a control character like `add_header`
+    pub fn redirect(&mut self, redirect: Redirect, location: &str) -> &mut Self {
+        let (code, msg) = redirect.status();
+        self.status_code(code, msg);
+        self.add_header("Location", location);
+        self.add_header("Content-Type", "text/html; charset=utf-8");
+        let mut href = String::with_capacity(location.len());
+        for c in location.chars() {
+            match c {
+                '&' => href.push_str("&amp;"),
+                '<' => href.push_str("&lt;"),
+                '>' => href.push_str("&gt;"),
+                '"' => href.push_str("&quot;"),
+                '\'' => href.push_str("&#39;"),
+                c => href.push(c),
+            }
+        }
+        self.body_vec(format!("Redirecting to <a href=\"{0}\">{0}</a>\n", href).into_bytes());
+        self
+    }
+
+    /// set a `text/plain` body
+    pub fn text(&mut self, s: impl Into<Cow<'static, str>>) -> &mut Self {
+        self.add_header("Content-Type", "text/plain; charset=utf-8");
+        match s.into() {
+            Cow::Borrowed(s) => self.body(s),
+            Cow::Owned(s) => self.body_vec(s.into_bytes()),
+        }
+        self
+    }
+
+    /// serialize the value as the body and set `Content-Type: application/json`
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> io::Result<&mut Self> {
+        self.clear_body();
+        self.body = Body::Dummy;
+        serde_json::to_writer(BodyWriter(self.rsp_buf), value)?;
+        self.add_header("Content-Type", "application/json");
+        Ok(self)
+    }
+
     pub fn body(&mut self, s: &'static str) {
         self.body = Body::Str(s);
     }
@@ -63,7 +276,7 @@ impl<'a> Response<'a> {
 
     pub fn body_mut(&mut self) -> &mut BytesMut {
         match self.body {
-            Body::Dummy => {}
+            Body::Dummy | Body::Chunked { .. } => {}
             Body::Str(s) => {
                 self.rsp_buf.extend_from_slice(s.as_bytes());
                 self.body = Body::Dummy;
@@ -73,12 +286,368 @@ impl<'a> Response<'a> {
                 self.body = Body::Dummy;
             }
         }
+        if self.chunked {
+            // what's written after this call is the next chunk
+            self.chunk_starts.push(self.rsp_buf.len());
+        }
         self.rsp_buf
     }
 
+    /// switch the response to `Transfer-Encoding: chunked` and stream the body
+    ///
+    /// the status and headers must be set before this, the body set before
+    /// is discarded. the data written to the returned writer is sent to the
+    /// client while the service is running, if the writer is not finished the
+    /// final chunk is sent after `call` returns
+    ///
+    /// a HTTP/1.0 client doesn't know chunked, the data is sent as it is and
+    /// the connection is closed at the end of the body
+    pub fn stream_chunked(&mut self) -> ChunkWriter<'_, 'a> {
+        if !self.is_chunked() {
+            self.body = Body::Chunked { finished: false };
+            self.rsp_buf.clear();
+            let framing = if self.version == Version::Http10 {
+                self.keep_alive = false;
+                Framing::None
+            } else {
+                Framing::Chunked
+            };
+            encode_head(
+                &self.status_message,
+                &self.headers[..self.headers_len],
+                &self.dyn_headers,
+                self.server,
+                self.connection(),
+                framing,
+                self.rsp_buf,
+            );
+        }
+        ChunkWriter { rsp: self }
+    }
+
+    /// stream the body like `stream_chunked`, compressed on the fly
+    ///
+    /// the encoding is picked from `Accept-Encoding` like `auto_compress`
+    /// does and `Content-Encoding` is added to the head. the data is sent
+    /// as it is when the client accepts no encoding, the content type is
+    /// already compressed or the body is already streaming. `Vary:
+    /// Accept-Encoding` is added unless one of the last two holds. like a
+    /// chunk writer left unfinished, a dropped writer ends the compressed
+    /// stream
+    #[cfg(feature = "compress")]
+    pub fn stream_compressed(&mut self) -> CompressWriter<'_, 'a> {
+        let negotiated = !self.is_chunked() && self.is_compressible();
+        if negotiated {
+            // like `compress_body`, also when the client gets it as it is
+            self.add_header("Vary", "Accept-Encoding");
+        }
+        let encoding = self.compress.encoding.filter(|_| negotiated);
+        if let Some(encoding) = encoding {
+            self.add_header("Content-Encoding", encoding.name());
+        }
+        CompressWriter {
+            encoder: crate::compress::StreamEncoder::new(encoding, self.stream_chunked()),
+        }
+    }
+
+    /// stream the body from the reader without buffering all of it
+    ///
+    /// with a known `len` it's sent with a `Content-Length` in bounded writes,
+    /// otherwise it's chunked like `stream_chunked`. the status and headers
+    /// must be set before this. the head is sent before the reader is read, so
+    /// a read error or a reader shorter than `len` closes the connection and
+    /// the client sees an incomplete body
+    pub fn body_from_reader(
+        &mut self,
+        mut reader: impl io::Read,
+        len: Option<u64>,
+    ) -> io::Result<()> {
+        let len = match len {
+            Some(len) => len,
+            None => {
+                let mut body = self.stream_chunked();
+                io::copy(&mut reader, &mut body)?;
+                return body.finish();
+            }
+        };
+        self.send_head(len)?;
+        if self.head {
+            return Ok(());
+        }
+        let mut writer = ChunkWriter { rsp: self };
+        let mut left = len;
+        while left > 0 {
+            let buf = &mut *writer.rsp.rsp_buf;
+            let want = left.min(CHUNK_FLUSH_SIZE as u64) as usize;
+            buf.resize(want, 0);
+            let res = match reader.read(buf) {
+                Ok(0) => {
+                    let msg = "the reader ended before the content length";
+                    Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(0),
+                res => res,
+            };
+            let n = match res {
+                Ok(n) => n,
+                Err(e) => {
+                    // the client can't tell where the body ends anymore
+                    buf.clear();
+                    writer.rsp.keep_alive = false;
+                    return Err(e);
+                }
+            };
+            buf.truncate(n);
+            writer.send()?;
+            left -= n as u64;
+        }
+        Ok(())
+    }
+
+    /// send `len` bytes of the file from `offset` as the body
+    ///
+    /// on linux the file goes straight to the socket with `sendfile`, elsewhere,
+    /// over tls or with a write timeout it's streamed like `body_from_reader`. the status and
+    /// headers must be set before this, the file must have the bytes
+    pub fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(stream) = self.stream {
+            // waiting on `sendfile` can't time out
+            let socket = {
+                let stream = stream.borrow();
+                let timeout = stream.write_timeout()?;
+                stream.raw_socket().filter(|_| timeout.is_none())
+            };
+            if let Some(socket) = socket {
+                self.send_head(len)?;
+                if self.head {
+                    return Ok(());
+                }
+                let (sent, res) = sendfile_wait(&*stream.borrow(), socket, file, offset, len);
+                self.sent += sent as usize;
+                if res.is_err() {
+                    // the client can't tell where the body ends anymore
+                    self.keep_alive = false;
+                }
+                return res;
+            }
+        }
+
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        self.body_from_reader(file.take(len), Some(len))
+    }
+
+    /// send the head of a body with a `Content-Length` streamed after it
+    fn send_head(&mut self, len: u64) -> io::Result<()> {
+        if self.is_chunked() {
+            let msg = "the body is already streamed";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        self.body = Body::Chunked { finished: true };
+        self.rsp_buf.clear();
+        encode_head(
+            &self.status_message,
+            &self.headers[..self.headers_len],
+            &self.dyn_headers,
+            self.server,
+            self.connection(),
+            Framing::Length(len),
+            self.rsp_buf,
+        );
+        ChunkWriter { rsp: self }.send()
+    }
+
+    /// send the streamed data buffered so far, parking the coroutine until the
+    /// socket takes it all
+    ///
+    /// it's the same as `ChunkWriter::flush`, and also sends the head when
+    /// nothing is written yet. a response that is not streamed is sent after
+    /// `call` returns, flushing it does nothing
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.body {
+            Body::Chunked { .. } if !self.rsp_buf.is_empty() => ChunkWriter { rsp: self }.send(),
+            _ => Ok(()),
+        }
+    }
+
+    /// send the body with `Transfer-Encoding: chunked` instead of a `Content-Length`
+    ///
+    /// the body is still buffered and sent after `call` returns, but each
+    /// `body_mut` call after this one starts a new chunk, a body set with
+    /// `body` or `body_vec` is a single chunk. use `stream_chunked` to send the
+    /// chunks as they're written. a `Content-Length` set by the service is
+    /// dropped, a HTTP/1.0 client gets the body with its length
+    pub fn set_chunked(&mut self) -> &mut Self {
+        self.chunked = true;
+        self
+    }
+
+    /// close the connection after this response, it's sent with `Connection: close`
+    ///
+    /// it overrides the keep-alive of the request, like after an auth failure.
+    /// a streamed response must call it before `stream_chunked` for the header
+    pub fn close_connection(&mut self) -> &mut Self {
+        self.keep_alive = false;
+        self
+    }
+
+    /// announce a trailer header sent after a chunked body, in the `Trailer` header
+    ///
+    /// it must be called before `stream_chunked`, the value is sent with
+    /// `ChunkWriter::finish_with_trailers`
+    pub fn trailer(&mut self, name: &str) -> &mut Self {
+        self.add_header("Trailer", name)
+    }
+
+    /// stream the body as server-sent events
+    ///
+    /// it sets `Content-Type: text/event-stream` and `Cache-Control: no-cache`
+    /// and streams the events like `stream_chunked`, each one is sent as soon
+    /// as it's produced
+    pub fn sse(&mut self) -> SseWriter<'_, 'a> {
+        if !self.is_chunked() {
+            self.add_header("Content-Type", "text/event-stream");
+            self.add_header("Cache-Control", "no-cache");
+        }
+        SseWriter {
+            chunks: self.stream_chunked(),
+        }
+    }
+
+    /// switch the connection to another protocol once this response is sent
+    ///
+    /// the response becomes a `101 Switching Protocols` with `Connection: Upgrade`
+    /// and no body, the service adds the `Upgrade` header and the others the
+    /// protocol needs. after the head is written the connection loop exits, and
+    /// `on_upgrade` takes over the stream in the connection coroutine. the
+    /// connection is closed when it returns
+    pub fn upgrade<F: FnOnce(Upgraded) + 'static>(&mut self, on_upgrade: F) -> &mut Self {
+        self.status_code("101", "Switching Protocols");
+        self.add_header("Connection", "Upgrade");
+        self.keep_alive = true;
+        self.upgrade = Some(Box::new(on_upgrade));
+        self
+    }
+
+    /// accept a websocket handshake and upgrade the connection, see `upgrade`
+    ///
+    /// the request is checked for the `Upgrade: websocket`, `Connection: Upgrade`,
+    /// `Sec-WebSocket-Version: 13` and `Sec-WebSocket-Key` headers, the
+    /// `Sec-WebSocket-Accept` is computed from the key. return false and leave
+    /// the response as it is if the request is not a valid handshake. the
+    /// websocket framing is up to `on_upgrade`
+    #[cfg(feature = "websocket")]
+    pub fn upgrade_websocket<F: FnOnce(Upgraded) + 'static>(
+        &mut self,
+        req: &crate::Request,
+        on_upgrade: F,
+    ) -> bool {
+        match crate::upgrade::websocket_accept(req) {
+            Some(accept) => {
+                self.upgrade(on_upgrade)
+                    .add_header("Upgrade", "websocket")
+                    .add_header("Sec-WebSocket-Accept", &accept);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        self.upgrade.take()
+    }
+
+    /// compress the body if the client accepts it, default is off
+    ///
+    /// the encoding is picked from `Accept-Encoding` among gzip, deflate
+    /// and br with the `brotli` feature. bodies smaller than
+    /// `HttpServer::compress_min_size`, already compressed content types and
+    /// streamed bodies are sent as they are
+    #[cfg(feature = "compress")]
+    pub fn auto_compress(&mut self, enable: bool) -> &mut Self {
+        self.compress.enabled = enable;
+        self
+    }
+
+    pub(crate) fn set_if_none_match(&mut self, value: Option<&[u8]>) {
+        self.if_none_match = value
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(str::to_owned);
+    }
+
+    #[cfg(feature = "compress")]
+    pub(crate) fn set_compression(
+        &mut self,
+        encoding: Option<crate::compress::Encoding>,
+        min_size: usize,
+    ) {
+        self.compress.encoding = encoding;
+        self.compress.min_size = min_size;
+    }
+
+    pub(crate) fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    pub(crate) fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
+    /// the `Connection` header value, a HTTP/1.0 connection is only kept when it's said
+    fn connection(&self) -> Option<&'static str> {
+        if !self.keep_alive {
+            Some("close")
+        } else if self.version == Version::Http10 {
+            Some("keep-alive")
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn is_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// omit the body bytes while keeping the headers as they are for a GET
+    pub(crate) fn set_head(&mut self, head: bool) {
+        self.head = head;
+    }
+
+    pub(crate) fn set_server(&mut self, server: Option<&'a str>) {
+        self.server = server;
+    }
+
+    pub(crate) fn set_stream(&mut self, stream: &'a Conn) {
+        self.stream = Some(stream);
+    }
+
+    pub(crate) fn set_corked(&mut self, corked: bool) {
+        self.corked = corked;
+    }
+
+    /// answer with `204 No Content` if the service left both the status and the body unset
+    pub(crate) fn set_no_content_default(&mut self) {
+        if !self.status_set && !self.is_chunked() && self.body_len() == 0 {
+            self.status_code("204", "No Content");
+        }
+    }
+
+    pub(crate) fn code(&self) -> &'static str {
+        self.status_message.code
+    }
+
+    pub(crate) fn bytes_sent(&self) -> usize {
+        self.sent
+    }
+
+    pub(crate) fn is_chunked(&self) -> bool {
+        matches!(self.body, Body::Chunked { .. })
+    }
+
     fn body_len(&self) -> usize {
         match self.body {
-            Body::Dummy => self.rsp_buf.len(),
+            Body::Dummy | Body::Chunked { .. } => self.rsp_buf.len(),
             Body::Str(s) => s.len(),
             Body::Vec(ref v) => v.len(),
         }
@@ -86,7 +655,7 @@ impl<'a> Response<'a> {
 
     fn get_body(&mut self) -> &[u8] {
         match self.body {
-            Body::Dummy => self.rsp_buf.as_ref(),
+            Body::Dummy | Body::Chunked { .. } => self.rsp_buf.as_ref(),
             Body::Str(s) => s.as_bytes(),
             Body::Vec(ref v) => v,
         }
@@ -94,37 +663,511 @@ impl<'a> Response<'a> {
 
     fn clear_body(&mut self) {
         match self.body {
-            Body::Dummy => self.rsp_buf.clear(),
+            Body::Dummy | Body::Chunked { .. } => self.rsp_buf.clear(),
             Body::Str(_) => {}
             Body::Vec(_) => {}
         }
     }
+
+    /// the name and value of all the headers set
+    fn header_lines(&self) -> impl Iterator<Item = (&str, &str)> {
+        header_lines(&self.headers[..self.headers_len], &self.dyn_headers)
+    }
+
+    /// set the etag of the body if the service asked for it
+    fn apply_auto_etag(&mut self) {
+        if !self.auto_etag || self.status_message.code != "200" {
+            return;
+        }
+        if self
+            .header_lines()
+            .any(|(n, _)| n.eq_ignore_ascii_case("etag"))
+        {
+            return;
+        }
+        let etag = crate::etag::content_etag(self.get_body());
+        self.etag(&etag);
+    }
+
+    /// replace the body with the compressed one when it's worth it
+    #[cfg(feature = "compress")]
+    fn compress_body(&mut self) {
+        let compress = &self.compress;
+        if !compress.enabled || self.body_len() < compress.min_size || !self.is_compressible() {
+            return;
+        }
+
+        // the body depends on `Accept-Encoding` also when this client gets it
+        // as it is, a cache must not serve it to the others
+        self.add_header("Vary", "Accept-Encoding");
+        let encoding = match self.compress.encoding {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let body = crate::compress::compress(encoding, self.get_body());
+        if body.len() >= self.body_len() {
+            return;
+        }
+        self.clear_body();
+        self.body = Body::Vec(body);
+        self.add_header("Content-Encoding", encoding.name());
+    }
+
+    /// the body is neither encoded by the service nor of a compressed type
+    #[cfg(feature = "compress")]
+    fn is_compressible(&self) -> bool {
+        !self.header_lines().any(|(name, value)| {
+            name.eq_ignore_ascii_case("content-encoding")
+                || (name.eq_ignore_ascii_case("content-type")
+                    && crate::compress::is_compressed_type(value))
+        })
+    }
+}
+
+/// split the static and the dynamic header lines into the names and values
+fn header_lines<'h>(
+    headers: &'h [&'static str],
+    dyn_headers: &'h str,
+) -> impl Iterator<Item = (&'h str, &'h str)> {
+    headers
+        .iter()
+        .copied()
+        .chain(dyn_headers.split("\r\n").skip(1))
+        .map(|h| {
+            let (name, value) = h.split_once(':').unwrap_or((h, ""));
+            (name.trim(), value.trim())
+        })
 }
 
-pub fn encode(mut msg: Response, buf: &mut BytesMut) {
-    if msg.status_message.msg == "Ok" {
-        buf.extend_from_slice(b"HTTP/1.1 200 Ok\r\nServer: may\r\nDate: ");
+/// a header name must be a token
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// a header value may have tabs but no other control characters
+fn is_valid_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b == b'\t' || (b >= 0x20 && b != 0x7f))
+}
+
+/// a header line that frames the body, those are set by the encoder
+fn is_framing(header: &str) -> bool {
+    let name = header
+        .split_once(':')
+        .map_or(header, |(name, _)| name)
+        .trim();
+    name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("transfer-encoding")
+}
+
+/// how the end of the body is told to the client
+enum Framing {
+    Length(u64),
+    Chunked,
+    /// no body, like when the connection switches to another protocol,
+    /// or one that ends when the connection is closed
+    None,
+}
+
+fn encode_head(
+    status: &StatusMessage,
+    headers: &[&'static str],
+    dyn_headers: &str,
+    server: Option<&str>,
+    connection: Option<&str>,
+    framing: Framing,
+    buf: &mut BytesMut,
+) {
+    if status.msg == "Ok" {
+        buf.extend_from_slice(b"HTTP/1.1 200 Ok");
     } else {
         buf.extend_from_slice(b"HTTP/1.1 ");
-        buf.extend_from_slice(msg.status_message.code.as_bytes());
+        buf.extend_from_slice(status.code.as_bytes());
         buf.extend_from_slice(b" ");
-        buf.extend_from_slice(msg.status_message.msg.as_bytes());
-        buf.extend_from_slice(b"\r\nServer: may\r\nDate: ");
+        buf.extend_from_slice(status.msg.as_bytes());
+    }
+    // the service may send its own, like the ones of a proxied response
+    let has_header = |header: &str| {
+        header_lines(headers, dyn_headers).any(|(name, _)| name.eq_ignore_ascii_case(header))
+    };
+    if let Some(server) = server.filter(|_| !has_header("server")) {
+        buf.extend_from_slice(b"\r\nServer: ");
+        buf.extend_from_slice(server.as_bytes());
+    }
+    if !has_header("date") {
+        buf.extend_from_slice(b"\r\nDate: ");
+        crate::date::set_date(buf);
+    }
+    match framing {
+        Framing::Length(len) => {
+            buf.extend_from_slice(b"\r\nContent-Length: ");
+            let mut length = itoa::Buffer::new();
+            buf.extend_from_slice(length.format(len).as_bytes());
+        }
+        Framing::Chunked => buf.extend_from_slice(b"\r\nTransfer-Encoding: chunked"),
+        Framing::None => {}
+    }
+
+    if let Some(connection) = connection {
+        buf.extend_from_slice(b"\r\nConnection: ");
+        buf.extend_from_slice(connection.as_bytes());
     }
-    crate::date::set_date(buf);
-    buf.extend_from_slice(b"\r\nContent-Length: ");
-    let mut length = itoa::Buffer::new();
-    buf.extend_from_slice(length.format(msg.body_len()).as_bytes());
 
-    for i in 0..msg.headers_len {
-        let h = *unsafe { msg.headers.get_unchecked(i) };
+    // the framing above is the only one sent, a `Content-Length` or
+    // `Transfer-Encoding` set by the service could disagree with the body
+    for h in headers.iter().filter(|h| !is_framing(h)) {
         buf.extend_from_slice(b"\r\n");
         buf.extend_from_slice(h.as_bytes());
     }
+    if dyn_headers.split("\r\n").any(is_framing) {
+        for h in dyn_headers.split("\r\n").skip(1).filter(|h| !is_framing(h)) {
+            buf.extend_from_slice(b"\r\n");
+            buf.extend_from_slice(h.as_bytes());
+        }
+    } else {
+        buf.extend_from_slice(dyn_headers.as_bytes());
+    }
 
     buf.extend_from_slice(b"\r\n\r\n");
-    buf.extend_from_slice(msg.get_body());
+}
+
+pub fn encode(msg: Response, buf: &mut BytesMut) {
+    let _ = encode_body_with(msg, buf, |buf, body| {
+        buf.extend_from_slice(body);
+        Ok(())
+    });
+}
+
+/// like `encode`, but a body that would take `buf` past `limit` is written to
+/// `stream` right after the bytes in `buf` instead of being copied into it
+///
+/// return the size of the encoded response
+pub(crate) fn encode_bounded(
+    msg: Response,
+    buf: &mut BytesMut,
+    limit: usize,
+    stream: &Conn,
+) -> io::Result<usize> {
+    encode_body_with(msg, buf, |buf, body| {
+        if buf.len() + body.len() <= limit {
+            buf.extend_from_slice(body);
+            return Ok(());
+        }
+        let mut stream = stream.borrow_mut();
+        write_wait(&mut *stream, buf)?;
+        buf.clear();
+        write_wait(&mut *stream, body)
+    })
+}
+
+/// encode the response into `buf`, the body is passed to `put_body`
+///
+/// return the size of the encoded response
+fn encode_body_with(
+    mut msg: Response,
+    buf: &mut BytesMut,
+    mut put_body: impl FnMut(&mut BytesMut, &[u8]) -> io::Result<()>,
+) -> io::Result<usize> {
+    let start = buf.len();
+    if let Body::Chunked { finished } = msg.body {
+        // the head is encoded by `stream_chunked`, only the unsent chunks are left
+        buf.extend_from_slice(msg.rsp_buf);
+        if !finished && !msg.head && msg.version == Version::Http11 {
+            buf.extend_from_slice(b"0\r\n\r\n");
+        }
+        msg.clear_body();
+        return Ok(buf.len() - start);
+    }
+
+    msg.apply_auto_etag();
+
+    // an informational response has no body, like the `101` of an upgrade,
+    // and neither has a `204` or a `304`
+    let code = msg.status_message.code;
+    if code.starts_with('1') || code == "204" || code == "304" {
+        encode_head(
+            &msg.status_message,
+            &msg.headers[..msg.headers_len],
+            &msg.dyn_headers,
+            msg.server,
+            msg.connection(),
+            Framing::None,
+            buf,
+        );
+        msg.clear_body();
+        return Ok(buf.len() - start);
+    }
+
+    #[cfg(feature = "compress")]
+    msg.compress_body();
+
+    let chunked = msg.chunked && msg.version == Version::Http11;
+    let framing = if chunked {
+        Framing::Chunked
+    } else {
+        Framing::Length(msg.body_len() as u64)
+    };
+    encode_head(
+        &msg.status_message,
+        &msg.headers[..msg.headers_len],
+        &msg.dyn_headers,
+        msg.server,
+        msg.connection(),
+        framing,
+        buf,
+    );
+    if msg.head {
+        msg.clear_body();
+        return Ok(buf.len() - start);
+    }
+    if !chunked {
+        let len = buf.len() - start + msg.body_len();
+        let res = put_body(buf, msg.get_body());
+        msg.clear_body();
+        return res.map(|_| len);
+    }
+
+    // each `body_mut` write is a chunk, a body set at once is a single one
+    let starts = match msg.body {
+        Body::Dummy => std::mem::take(&mut msg.chunk_starts),
+        _ => Vec::new(),
+    };
+    let mut len = buf.len() - start;
+    let body = msg.get_body();
+    let mut from = 0;
+    let mut res = Ok(());
+    for end in starts.into_iter().chain([body.len()]) {
+        if end <= from || end > body.len() {
+            continue;
+        }
+        let chunk = &body[from..end];
+        from = end;
+        let size_start = buf.len();
+        write!(buf, "{:x}\r\n", chunk.len()).unwrap();
+        len += buf.len() - size_start + chunk.len() + 2;
+        res = put_body(buf, chunk);
+        if res.is_err() {
+            break;
+        }
+        buf.extend_from_slice(b"\r\n");
+    }
     msg.clear_body();
+    res?;
+    buf.extend_from_slice(b"0\r\n\r\n");
+    Ok(len + 5)
+}
+
+/// the size of the buffered chunks that triggers sending them
+const CHUNK_FLUSH_SIZE: usize = 4096 * 8;
+
+/// the writer of a `Transfer-Encoding: chunked` response body
+///
+/// it's returned by `Response::stream_chunked`, each write is framed as a chunk.
+/// the chunks are buffered and sent when they grow large or on `flush`
+pub struct ChunkWriter<'r, 'a> {
+    rsp: &'r mut Response<'a>,
+}
+
+impl<'r, 'a> ChunkWriter<'r, 'a> {
+    /// send the final chunk, the response body is complete after this
+    pub fn finish(self) -> io::Result<()> {
+        self.finish_with_trailers(&[])
+    }
+
+    /// send the final chunk followed by the trailer headers
+    ///
+    /// the names should be announced with `Response::trailer` first. panic
+    /// on an invalid name or value like `Response::add_header`
+    pub fn finish_with_trailers(mut self, trailers: &[(&str, &str)]) -> io::Result<()> {
+        if !self.rsp.head && self.rsp.version == Version::Http11 {
+            let buf = &mut *self.rsp.rsp_buf;
+            buf.extend_from_slice(b"0\r\n");
+            for (name, value) in trailers {
+                assert!(is_valid_name(name), "invalid trailer name: {:?}", name);
+                assert!(is_valid_value(value), "invalid trailer value: {:?}", value);
+                buf.extend_from_slice(name.as_bytes());
+                buf.extend_from_slice(b": ");
+                buf.extend_from_slice(value.as_bytes());
+                buf.extend_from_slice(b"\r\n");
+            }
+            buf.extend_from_slice(b"\r\n");
+        }
+        self.rsp.body = Body::Chunked { finished: true };
+        self.send()
+    }
+
+    /// send the buffered chunks to the client
+    fn send(&mut self) -> io::Result<()> {
+        let stream = self
+            .rsp
+            .stream
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        let mut stream = stream.borrow_mut();
+        let mut res =
+            write_wait(&mut *stream, self.rsp.rsp_buf).and_then(|_| flush_wait(&mut *stream));
+        if self.rsp.corked && res.is_ok() {
+            // push out what's held back, the client may wait for this chunk
+            res = stream.set_cork(false).and_then(|_| stream.set_cork(true));
+        }
+        self.rsp.sent += self.rsp.rsp_buf.len();
+        self.rsp.rsp_buf.clear();
+        if res.is_err() {
+            // the connection is broken, nothing more should be sent
+            self.rsp.body = Body::Chunked { finished: true };
+            self.rsp.keep_alive = false;
+        }
+        res
+    }
+}
+
+impl<'r, 'a> io::Write for ChunkWriter<'r, 'a> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the body, a HEAD response has none
+        if data.is_empty() || self.rsp.head {
+            return Ok(data.len());
+        }
+        let buf = &mut *self.rsp.rsp_buf;
+        if self.rsp.version == Version::Http10 {
+            buf.extend_from_slice(data);
+        } else {
+            write!(buf, "{:x}\r\n", data.len()).unwrap();
+            buf.extend_from_slice(data);
+            buf.extend_from_slice(b"\r\n");
+        }
+        if buf.len() >= CHUNK_FLUSH_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+/// the writer of a compressed `Transfer-Encoding: chunked` response body
+///
+/// it's returned by `Response::stream_compressed`, the data is compressed as
+/// it's written and the compressed output is framed as chunks. `flush` sends
+/// what's compressed so far, at the cost of a worse compression
+#[cfg(feature = "compress")]
+pub struct CompressWriter<'r, 'a> {
+    encoder: crate::compress::StreamEncoder<ChunkWriter<'r, 'a>>,
+}
+
+#[cfg(feature = "compress")]
+impl<'r, 'a> CompressWriter<'r, 'a> {
+    /// end the compressed stream and send the final chunk
+    pub fn finish(self) -> io::Result<()> {
+        self.encoder.finish()?.finish()
+    }
+
+    /// end the compressed stream and send the final chunk with the trailers
+    ///
+    /// see `ChunkWriter::finish_with_trailers`
+    pub fn finish_with_trailers(self, trailers: &[(&str, &str)]) -> io::Result<()> {
+        self.encoder.finish()?.finish_with_trailers(trailers)
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<'r, 'a> io::Write for CompressWriter<'r, 'a> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.encoder.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+/// the writer of a server-sent events response
+///
+/// it's returned by `Response::sse`, an error from sending an event usually
+/// means the client is gone and the service should stop
+pub struct SseWriter<'r, 'a> {
+    chunks: ChunkWriter<'r, 'a>,
+}
+
+impl<'r, 'a> SseWriter<'r, 'a> {
+    /// send an event with the given name, an empty name sends an unnamed `message`
+    ///
+    /// each line of `data` becomes a `data:` field
+    pub fn send_event(&mut self, name: &str, data: &str) -> io::Result<()> {
+        if name.contains(['\r', '\n']) {
+            let msg = "event name contains a line break";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        let mut event = String::with_capacity(name.len() + data.len() + 16);
+        if !name.is_empty() {
+            event.push_str("event: ");
+            event.push_str(name);
+            event.push('\n');
+        }
+        for line in data.lines() {
+            event.push_str("data: ");
+            event.push_str(line);
+            event.push('\n');
+        }
+        if data.is_empty() {
+            event.push_str("data:\n");
+        }
+        event.push('\n');
+        self.send(&event)
+    }
+
+    /// send a comment line, clients ignore it but it keeps idle proxies from closing the stream
+    pub fn send_comment(&mut self, comment: &str) -> io::Result<()> {
+        let mut event = String::with_capacity(comment.len() + 4);
+        for line in comment.lines() {
+            event.push_str(": ");
+            event.push_str(line);
+            event.push('\n');
+        }
+        event.push('\n');
+        self.send(&event)
+    }
+
+    /// end the event stream
+    pub fn finish(self) -> io::Result<()> {
+        self.chunks.finish()
+    }
+
+    fn send(&mut self, event: &str) -> io::Result<()> {
+        io::Write::write_all(&mut self.chunks, event.as_bytes())?;
+        io::Write::flush(&mut self.chunks)
+    }
+}
+
+/// the status of a redirect sent with `Response::redirect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redirect {
+    /// `301 Moved Permanently`, the client may change a POST to a GET
+    MovedPermanently,
+    /// `302 Found`, the client may change a POST to a GET
+    Found,
+    /// `303 See Other`, the client follows with a GET, like after a form is posted
+    SeeOther,
+    /// `307 Temporary Redirect`, the method and body are kept
+    Temporary,
+    /// `308 Permanent Redirect`, the method and body are kept
+    Permanent,
+}
+
+impl Redirect {
+    fn status(self) -> (&'static str, &'static str) {
+        match self {
+            Redirect::MovedPermanently => ("301", "Moved Permanently"),
+            Redirect::Found => ("302", "Found"),
+            Redirect::SeeOther => ("303", "See Other"),
+            Redirect::Temporary => ("307", "Temporary Redirect"),
+            Redirect::Permanent => ("308", "Permanent Redirect"),
+        }
+    }
 }
 
 // impl io::Write for the response body
@@ -141,3 +1184,125 @@ impl<'a> io::Write for BodyWriter<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(f: impl FnOnce(&mut Response)) -> String {
+        let mut body_buf = BytesMut::new();
+        let mut rsp = Response::new(&mut body_buf);
+        f(&mut rsp);
+        let mut buf = BytesMut::new();
+        encode(rsp, &mut buf);
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+
+    const NAMES: [&str; 20] = [
+        "X-0: 0", "X-1: 1", "X-2: 2", "X-3: 3", "X-4: 4", "X-5: 5", "X-6: 6", "X-7: 7", "X-8: 8",
+        "X-9: 9", "X-10: 10", "X-11: 11", "X-12: 12", "X-13: 13", "X-14: 14", "X-15: 15",
+        "X-16: 16", "X-17: 17", "X-18: 18", "X-19: 19",
+    ];
+
+    #[test]
+    fn more_than_16_headers_are_all_sent() {
+        let rsp = encoded(|rsp| {
+            for h in NAMES {
+                rsp.header(h);
+            }
+            rsp.add_header("X-Added", "a");
+        });
+        for h in NAMES {
+            assert!(
+                rsp.contains(&format!("\r\n{}\r\n", h)),
+                "{} in {:?}",
+                h,
+                rsp
+            );
+        }
+        assert!(rsp.contains("\r\nX-Added: a\r\n"));
+    }
+
+    #[test]
+    fn text_sets_the_content_type() {
+        let rsp = encoded(|rsp| {
+            rsp.text(format!("{}", 42));
+        });
+        assert!(rsp.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n42"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_sets_the_content_type() {
+        let rsp = encoded(|rsp| {
+            rsp.json(&[1, 2]).unwrap();
+        });
+        assert!(rsp.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n[1,2]"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid header value")]
+    fn add_header_rejects_a_crlf() {
+        encoded(|rsp| {
+            rsp.add_header("X-Name", "a\r\nSet-Cookie: b");
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid header name")]
+    fn add_header_rejects_a_bad_name() {
+        encoded(|rsp| {
+            rsp.add_header("X Name", "a");
+        });
+    }
+
+    /// the values of the header lines with the given name in the head of `rsp`
+    fn headers<'r>(rsp: &'r str, name: &str) -> Vec<&'r str> {
+        let head = rsp.split("\r\n\r\n").next().unwrap();
+        head.split("\r\n")
+            .skip(1)
+            .filter_map(|line| line.split_once(": "))
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    #[test]
+    fn manual_content_length_is_replaced() {
+        let rsp = encoded(|rsp| {
+            rsp.header("Content-Length: 3");
+            rsp.add_header("content-length", "7");
+            rsp.body("hello");
+        });
+        assert_eq!(headers(&rsp, "content-length"), ["5"]);
+        assert!(rsp.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn manual_transfer_encoding_is_replaced() {
+        let rsp = encoded(|rsp| {
+            rsp.header("Transfer-Encoding: chunked");
+            rsp.add_header("X-After", "1");
+            rsp.body("hello");
+        });
+        assert_eq!(headers(&rsp, "content-length"), ["5"]);
+        assert!(headers(&rsp, "transfer-encoding").is_empty());
+        assert_eq!(headers(&rsp, "x-after"), ["1"]);
+        assert!(rsp.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn framing_past_the_inline_headers_is_replaced() {
+        let rsp = encoded(|rsp| {
+            for h in NAMES {
+                rsp.header(h);
+            }
+            rsp.header("Content-Length: 1");
+            rsp.body("hello");
+        });
+        assert_eq!(headers(&rsp, "content-length"), ["5"]);
+        assert_eq!(headers(&rsp, "x-19"), ["19"]);
+    }
+}
@@ -0,0 +1,417 @@
+//! dispatching the requests to handlers by the method and path
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::http_server::HttpService;
+use crate::method::Method;
+use crate::request::Request;
+use crate::response::Response;
+
+/// the handler of a route
+pub type Handler = dyn Fn(Request, &mut Response) -> io::Result<()> + Send + Sync;
+
+/// a segment of a route pattern
+enum Segment {
+    Exact(String),
+    /// a `:name` segment that matches any one segment
+    Param(Arc<str>),
+}
+
+struct Route {
+    method: Method<'static>,
+    segments: Vec<Segment>,
+    handler: Arc<Handler>,
+    /// the time the requests have, see `Router::timeout`
+    timeout: Option<Duration>,
+}
+
+impl Route {
+    fn matches(&self, path: &[std::borrow::Cow<'_, str>]) -> bool {
+        self.segments.len() == path.len()
+            && self.segments.iter().zip(path).all(|(s, p)| match s {
+                Segment::Exact(s) => s == p,
+                Segment::Param(_) => true,
+            })
+    }
+}
+
+/// the values captured by the `:name` segments of the matched route
+///
+/// it's stored in the `Request::context` before the handler is called,
+/// `Request::param` is the shortcut to get one of them
+#[derive(Default)]
+pub struct Params {
+    values: Vec<(Arc<str>, String)>,
+}
+
+impl Params {
+    /// the value captured by the segment with the given name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// the names and values in the order of the segments
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(n, v)| (&**n, v.as_str()))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+/// a service that calls the handler of the first route matching the request
+///
+/// a pattern is matched segment by segment against `Request::path_segments`,
+/// a `:name` segment matches any segment and captures it into `Params`.
+/// a HEAD request is served by a GET route. a request matching no route is
+/// answered with `404 Not Found`, or with `405 Method Not Allowed` and the
+/// `Allow` header when a route of another method matches the path. an
+/// `OPTIONS` request without its own route gets the `Allow` header.
+/// the routes are shared, so cloning the router for each connection is cheap
+///
+/// ```no_run
+/// use may_minihttp::{HttpServer, Router};
+///
+/// let router = Router::new().get("/users/:id", |req, rsp| {
+///     rsp.text(format!("user {}", req.param("id").unwrap()));
+///     Ok(())
+/// });
+/// HttpServer(router).start("127.0.0.1:8080").unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Arc<Vec<Route>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add a route for the method and pattern
+    ///
+    /// the routes are tried in the order they are added
+    pub fn route<F>(mut self, method: Method<'static>, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Request, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.strip_prefix(':') {
+                Some(name) => Segment::Param(name.into()),
+                None => Segment::Exact(s.to_owned()),
+            })
+            .collect();
+        let route = Route {
+            method,
+            segments,
+            handler: Arc::new(handler),
+            timeout: None,
+        };
+        Arc::get_mut(&mut self.routes)
+            .expect("routes added to a cloned router")
+            .push(route);
+        self
+    }
+
+    /// set the time the requests of the route added last have to be answered
+    ///
+    /// it's for a route that needs more or less time than the others, like a
+    /// report that takes long to generate. the time counts from the route
+    /// being matched, the head is read with the timeout of the server before.
+    /// the reads of the body wait no longer than what's left of it, instead
+    /// of the `HttpServer::read_timeout`, and a handler returning after it is
+    /// answered with `503 Service Unavailable` and the connection closed.
+    /// the handler itself is not interrupted, and a response that's already
+    /// streaming when the time is up is left as it is
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use may_minihttp::{HttpServer, Router};
+    ///
+    /// let router = Router::new()
+    ///     .post("/reports", |mut req, rsp| {
+    ///         rsp.body_vec(req.body()?.to_vec());
+    ///         Ok(())
+    ///     })
+    ///     .timeout(Duration::from_secs(60));
+    /// HttpServer(router)
+    ///     .read_timeout(Some(Duration::from_secs(5)))
+    ///     .start("127.0.0.1:8080")
+    ///     .unwrap();
+    /// ```
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        Arc::get_mut(&mut self.routes)
+            .expect("routes added to a cloned router")
+            .last_mut()
+            .expect("timeout set before adding a route")
+            .timeout = Some(timeout);
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Request, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Request, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Request, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(Request, &mut Response) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// the methods allowed on the path, `None` when no route matches it
+    fn allowed(&self, path: &[std::borrow::Cow<'_, str>]) -> Option<Vec<Method<'static>>> {
+        let mut methods = Vec::new();
+        for route in self.routes.iter().filter(|r| r.matches(path)) {
+            let implied = match route.method {
+                Method::Get => Some(Method::Head),
+                _ => None,
+            };
+            for m in std::iter::once(route.method).chain(implied) {
+                if !methods.contains(&m) {
+                    methods.push(m);
+                }
+            }
+        }
+        if methods.is_empty() {
+            return None;
+        }
+        if !methods.contains(&Method::Options) {
+            methods.push(Method::Options);
+        }
+        Some(methods)
+    }
+}
+
+impl HttpService for Router {
+    fn call(&mut self, mut req: Request, rsp: &mut Response) -> io::Result<()> {
+        let path: Vec<_> = req.path_segments().collect();
+        let method = req.method();
+        let route = self.routes.iter().find(|r| {
+            (r.method == method || (method == Method::Head && r.method == Method::Get))
+                && r.matches(&path)
+        });
+        let route = match route {
+            Some(route) => route,
+            None => {
+                match self.allowed(&path) {
+                    Some(allowed) if method == Method::Options => {
+                        rsp.allow(&allowed);
+                    }
+                    Some(allowed) => {
+                        rsp.method_not_allowed(&allowed);
+                    }
+                    None => {
+                        rsp.status_code("404", "Not Found");
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        // reuse the allocation of the previous request on the connection
+        let params = req.context().get_or_default::<Params>();
+        params.clear();
+        for (s, p) in route.segments.iter().zip(&path) {
+            if let Segment::Param(name) = s {
+                params.values.push((name.clone(), p.clone().into_owned()));
+            }
+        }
+        if let Some(timeout) = route.timeout {
+            req.set_deadline(Some(Instant::now() + timeout));
+        }
+        (route.handler)(req, rsp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::http_server::HttpServer;
+    use crate::test;
+    use crate::test::slow::{self, SlowStream, Step};
+
+    /// the router for `/users`, the other paths show the param they see
+    struct App(Router);
+
+    impl HttpService for App {
+        fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+            if req.path().starts_with("/users/") {
+                return self.0.call(req, rsp);
+            }
+            rsp.body_vec(format!("other {:?}", req.param("id")).into_bytes());
+            Ok(())
+        }
+    }
+
+    fn app() -> App {
+        App(Router::new().get("/users/:id", |req, rsp| {
+            rsp.body_vec(format!("user {:?}", req.param("id")).into_bytes());
+            Ok(())
+        }))
+    }
+
+    fn bodies(rsp: &[u8]) -> Vec<String> {
+        let rsp = String::from_utf8_lossy(rsp);
+        rsp.split("HTTP/1.1 ")
+            .skip(1)
+            .map(|r| r.split_once("\r\n\r\n").unwrap().1.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn param_is_captured() {
+        let rsp = test::serve(app(), b"GET /users/4%202 HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(bodies(&rsp), ["user Some(\"4 2\")"]);
+    }
+
+    #[test]
+    fn unmatched_path_is_404() {
+        let rsp = test::serve(app(), b"GET /users/42/posts HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(rsp.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn params_are_not_kept_for_the_next_request() {
+        let req = b"GET /users/42 HTTP/1.1\r\nHost: a\r\n\r\n\
+            GET /users/42/posts HTTP/1.1\r\nHost: a\r\n\r\n\
+            GET /other HTTP/1.1\r\nHost: a\r\n\r\n\
+            GET /users/7 HTTP/1.1\r\nHost: a\r\n\r\n";
+        let rsp = test::serve(app(), req);
+        assert_eq!(
+            bodies(&rsp),
+            ["user Some(\"42\")", "", "other None", "user Some(\"7\")"]
+        );
+    }
+
+    type Bodies = Arc<Mutex<Vec<Result<Vec<u8>, io::ErrorKind>>>>;
+
+    /// `/slow` has 10s for the request, `/short` 2s and `/fast` reads the
+    /// body with the timeout of the server, 1s. return the bodies the routes
+    /// read and what's written
+    fn serve_pausing(steps: Vec<Step>) -> (Bodies, String) {
+        let bodies = Bodies::default();
+        let read = |bodies: &Bodies| {
+            let bodies = bodies.clone();
+            move |mut req: Request, _rsp: &mut Response| {
+                let body = req.body().map(<[u8]>::to_vec);
+                bodies
+                    .lock()
+                    .unwrap()
+                    .push(body.as_ref().map_err(|e| e.kind()).cloned());
+                body.map(drop)
+            }
+        };
+        let router = Router::new()
+            .post("/slow", read(&bodies))
+            .timeout(Duration::from_secs(10))
+            .post("/short", read(&bodies))
+            .timeout(Duration::from_secs(2))
+            .post("/fast", read(&bodies));
+        let server = HttpServer(router).read_timeout(Some(Duration::from_secs(1)));
+        let rsp = slow::serve(server, SlowStream::new(steps, usize::MAX));
+        (bodies, String::from_utf8(rsp).unwrap())
+    }
+
+    fn head(path: &str) -> Vec<u8> {
+        format!("POST {path} HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nab").into_bytes()
+    }
+
+    fn pause() -> Step {
+        Step::Pause(Duration::from_secs(3))
+    }
+
+    #[test]
+    fn route_timeout_covers_a_slow_body() {
+        let (bodies, _) = serve_pausing(vec![
+            Step::Send(head("/slow")),
+            pause(),
+            Step::Send(b"cde".to_vec()),
+            // the override is not kept for the next request
+            Step::Send(head("/fast")),
+            pause(),
+            Step::Send(b"cde".to_vec()),
+        ]);
+        assert_eq!(
+            *bodies.lock().unwrap(),
+            [Ok(b"abcde".to_vec()), Err(io::ErrorKind::TimedOut)]
+        );
+    }
+
+    #[test]
+    fn route_timeout_limits_the_body_reads() {
+        let (bodies, rsp) = serve_pausing(vec![
+            Step::Send(head("/short")),
+            pause(),
+            Step::Send(b"cde".to_vec()),
+        ]);
+        assert_eq!(*bodies.lock().unwrap(), [Err(io::ErrorKind::TimedOut)]);
+        assert!(
+            rsp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{rsp:?}"
+        );
+        assert!(rsp.contains("\r\nConnection: close\r\n"), "{rsp:?}");
+    }
+
+    #[test]
+    fn route_timeout_not_for_the_head() {
+        let (bodies, _) = serve_pausing(vec![
+            Step::Send(b"POST /slow HTTP/1.1\r\n".to_vec()),
+            pause(),
+            Step::Send(head("/slow")[21..].to_vec()),
+        ]);
+        assert!(bodies.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn late_handler_is_answered_with_503() {
+        let router = Router::new()
+            .get("/late", |_req, rsp| {
+                std::thread::sleep(Duration::from_millis(100));
+                rsp.body("late");
+                Ok(())
+            })
+            .timeout(Duration::from_millis(20))
+            .get("/", |_req, rsp| {
+                rsp.body("in time");
+                Ok(())
+            });
+        let req = b"GET /late HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let rsp = String::from_utf8(test::serve(router, req)).unwrap();
+        assert!(
+            rsp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{rsp:?}"
+        );
+        assert!(rsp.contains("\r\nConnection: close\r\n"), "{rsp:?}");
+        // the connection is closed after it
+        assert_eq!(rsp.matches("HTTP/1.1 ").count(), 1, "{rsp:?}");
+        assert!(!rsp.contains("late"), "{rsp:?}");
+    }
+}
@@ -0,0 +1,141 @@
+//! graceful shutdown of the http server
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use may::coroutine::{self, Coroutine};
+use may::net::TcpStream;
+
+/// the token to gracefully shutdown a running http server
+///
+/// it's returned by `HttpServer::start_with_shutdown`
+#[derive(Clone)]
+pub struct Shutdown(Arc<State>);
+
+impl Shutdown {
+    pub(crate) fn new(addr: SocketAddr) -> Self {
+        Shutdown(Arc::new(State {
+            stopping: AtomicBool::new(false),
+            grace: Mutex::new(None),
+            addr,
+            next_id: AtomicUsize::new(0),
+            connections: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    pub(crate) fn state(&self) -> Arc<State> {
+        self.0.clone()
+    }
+
+    /// stop accepting new connections and drain the existing ones
+    ///
+    /// each connection finishes the request it's processing and then closes,
+    /// idle keep-alive connections are closed right away. after the `grace`
+    /// duration the remaining connections are forcibly closed, without it the
+    /// server waits for all of them. join the server coroutine to wait until
+    /// the draining is done
+    pub fn shutdown(&self, grace: Option<Duration>) {
+        *self.0.grace.lock().unwrap() = grace;
+        if self.0.stopping.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // wake up the accept loop so that it can see the flag
+        let _ = TcpStream::connect(wake_addr(self.0.addr));
+    }
+}
+
+/// the address to connect to for waking up a listener bound on `addr`
+fn wake_addr(mut addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+        _ => {}
+    }
+    addr
+}
+
+pub(crate) struct State {
+    stopping: AtomicBool,
+    grace: Mutex<Option<Duration>>,
+    addr: SocketAddr,
+    next_id: AtomicUsize,
+    connections: Mutex<HashMap<usize, Arc<Connection>>>,
+}
+
+struct Connection {
+    co: Coroutine,
+    idle: AtomicBool,
+}
+
+impl State {
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::Acquire)
+    }
+
+    /// register the current connection coroutine
+    pub(crate) fn register(self: &Arc<Self>) -> ConnGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let conn = Arc::new(Connection {
+            co: coroutine::current(),
+            idle: AtomicBool::new(true),
+        });
+        self.connections.lock().unwrap().insert(id, conn.clone());
+        ConnGuard {
+            state: self.clone(),
+            id,
+            conn,
+        }
+    }
+
+    fn cancel(&self, f: impl Fn(&Connection) -> bool) {
+        for conn in self.connections.lock().unwrap().values() {
+            if f(conn) {
+                unsafe { conn.co.cancel() };
+            }
+        }
+    }
+
+    /// wait for all the connections to finish, called after the accept loop exits
+    pub(crate) fn drain(&self) {
+        let deadline = self
+            .grace
+            .lock()
+            .unwrap()
+            .map(|grace| Instant::now() + grace);
+        // idle connections have nothing in flight
+        self.cancel(|conn| conn.idle.load(Ordering::Acquire));
+        loop {
+            if self.connections.lock().unwrap().is_empty() {
+                return;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.cancel(|_| true);
+                return;
+            }
+            coroutine::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// the registration of a connection, removed when dropped
+pub(crate) struct ConnGuard {
+    state: Arc<State>,
+    id: usize,
+    conn: Arc<Connection>,
+}
+
+impl ConnGuard {
+    /// mark whether the connection is waiting for a new request
+    pub(crate) fn set_idle(&self, idle: bool) {
+        self.conn.idle.store(idle, Ordering::Release);
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.state.connections.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,293 @@
+//! serving the files of a directory
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use httpdate::HttpDate;
+
+use crate::etag::file_etag;
+use crate::http_server::HttpService;
+use crate::method::Method;
+use crate::range::ByteRange;
+use crate::request::Request;
+use crate::response::Response;
+
+/// a service that serves the files under a root directory
+///
+/// the request path is mapped to a file with `Request::path_segments`, so it
+/// never leaves the root, and a directory serves its `index.html`. the file
+/// is streamed with a `Content-Type` guessed from the extension, an `ETag`
+/// and a `Last-Modified` that are checked against the conditional headers,
+/// a single `Range` is sent as `206 Partial Content`. a missing file is
+/// answered with `404 Not Found`
+///
+/// ```no_run
+/// use may_minihttp::{HttpServer, StaticFiles};
+///
+/// HttpServer(StaticFiles::new("./public")).start("127.0.0.1:8080").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct StaticFiles {
+    root: Arc<PathBuf>,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            root: Arc::new(root.into()),
+        }
+    }
+
+    /// the file for the request path, `None` if a segment could escape the root
+    fn file_path(&self, req: &Request) -> Option<PathBuf> {
+        let mut path = (*self.root).clone();
+        for segment in req.path_segments() {
+            // a decoded segment may have a separator or a prefix like `C:`
+            if segment.contains(['/', '\\', '\0', ':']) {
+                return None;
+            }
+            path.push(&*segment);
+        }
+        if path.is_dir() {
+            path.push("index.html");
+        }
+        Some(path)
+    }
+}
+
+impl HttpService for StaticFiles {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+        if !matches!(req.method(), Method::Get | Method::Head) {
+            rsp.status_code("405", "Method Not Allowed");
+            rsp.add_header("Allow", "GET, HEAD");
+            return Ok(());
+        }
+
+        let file = self.file_path(&req).and_then(|path| {
+            let file = File::open(&path).ok()?;
+            let meta = file.metadata().ok()?;
+            meta.is_file().then_some((file, meta, path))
+        });
+        let (file, meta, path) = match file {
+            Some(file) => file,
+            None => {
+                rsp.status_code("404", "Not Found");
+                return Ok(());
+            }
+        };
+
+        if rsp.etag(&file_etag(&meta)) {
+            return Ok(());
+        }
+        let modified = meta.modified().ok().map(HttpDate::from);
+        if let Some(modified) = modified {
+            // `If-None-Match` takes the precedence when both are sent
+            let since = req
+                .header("if-modified-since")
+                .filter(|_| req.header("if-none-match").is_none())
+                .and_then(|v| std::str::from_utf8(v).ok())
+                .and_then(|v| v.parse::<HttpDate>().ok());
+            if since.is_some_and(|since| modified <= since) {
+                rsp.status_code("304", "Not Modified");
+                return Ok(());
+            }
+            let modified = httpdate::fmt_http_date(SystemTime::from(modified));
+            rsp.add_header("Last-Modified", &modified);
+        }
+        rsp.add_header("Content-Type", content_type(&path));
+        rsp.add_header("Accept-Ranges", "bytes");
+
+        let range = req.byte_range(meta.len());
+        rsp.content_range(&range, meta.len());
+        let (offset, len) = match range {
+            ByteRange::Full => (0, meta.len()),
+            ByteRange::Partial(range) => (range.start, range.end - range.start),
+            ByteRange::Unsatisfiable => return Ok(()),
+        };
+        rsp.send_file(&file, offset, len)
+    }
+}
+
+/// the content type for the extension of the file
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match &*ext {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test;
+    use std::fs;
+
+    /// a root directory with an index and a file in it, unique to the test
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "may_minihttp_static_{}_{}",
+            std::process::id(),
+            name
+        ));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "<p>home</p>").unwrap();
+        fs::write(root.join("docs").join("a.css"), "p {}").unwrap();
+        root
+    }
+
+    fn get(root: &Path, path: &str) -> String {
+        let req = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path);
+        let rsp = test::serve(StaticFiles::new(root), req.as_bytes());
+        String::from_utf8(rsp).unwrap()
+    }
+
+    #[test]
+    fn file_is_served_with_its_type() {
+        let root = root("file");
+        let rsp = get(&root, "/docs/a.css");
+        assert!(rsp.starts_with("HTTP/1.1 200 Ok\r\n"));
+        assert!(rsp.contains("\r\nContent-Type: text/css; charset=utf-8\r\n"));
+        assert!(rsp.contains("\r\nLast-Modified: "));
+        assert!(rsp.contains("\r\nContent-Length: 4\r\n"));
+        assert!(rsp.ends_with("\r\n\r\np {}"));
+        let rsp = get(&root, "/");
+        assert!(rsp.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"));
+        assert!(rsp.contains("<p>home</p>"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_404() {
+        let root = root("missing");
+        for path in ["/nope.txt", "/docs/", "/docs/a.css/x"] {
+            assert!(get(&root, path).starts_with("HTTP/1.1 404 Not Found\r\n"));
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn path_never_leaves_the_root() {
+        let root = root("traversal");
+        let secret = root.with_extension("secret");
+        fs::write(&secret, "secret").unwrap();
+        let name = secret.file_name().unwrap().to_str().unwrap();
+        for path in [
+            format!("/../{}", name),
+            format!("/docs/%2e%2e/..%2f{}", name),
+            format!("/..%5c{}", name),
+        ] {
+            let rsp = get(&root, &path);
+            assert!(!rsp.contains("secret"), "{}: {}", path, rsp);
+        }
+        fs::remove_file(secret).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    fn get_range(root: &Path, range: &str) -> String {
+        let req = format!(
+            "GET /docs/a.css HTTP/1.1\r\nHost: a\r\nRange: {}\r\n\r\n",
+            range
+        );
+        let rsp = test::serve(StaticFiles::new(root), req.as_bytes());
+        String::from_utf8(rsp).unwrap()
+    }
+
+    #[test]
+    fn range_is_206() {
+        let root = root("range");
+        let rsp = get_range(&root, "bytes=1-2");
+        assert!(rsp.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(rsp.contains("\r\nContent-Range: bytes 1-2/4\r\n"));
+        assert!(rsp.contains("\r\nAccept-Ranges: bytes\r\n"));
+        assert!(rsp.contains("\r\nContent-Length: 2\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n {"));
+        let rsp = get_range(&root, "bytes=-1");
+        assert!(rsp.contains("\r\nContent-Range: bytes 3-3/4\r\n"));
+        assert!(rsp.ends_with("\r\n\r\n}"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn range_out_of_the_file_is_416() {
+        let root = root("unsatisfiable");
+        let rsp = get_range(&root, "bytes=4-");
+        assert!(rsp.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(rsp.contains("\r\nContent-Range: bytes */4\r\n"));
+        assert!(!rsp.contains("p {}"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn unmodified_file_is_304() {
+        let root = root("modified");
+        let req = b"GET /docs/a.css HTTP/1.1\r\nHost: a\r\n\
+            If-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n\r\n\
+            GET /docs/a.css HTTP/1.1\r\nHost: a\r\n\
+            If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n";
+        let rsp = String::from_utf8(test::serve(StaticFiles::new(&root), req)).unwrap();
+        let (first, second) = rsp.split_at(rsp.rfind("HTTP/1.1 ").unwrap());
+        assert!(first.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(first.ends_with("\r\n\r\n"));
+        assert!(second.starts_with("HTTP/1.1 200 Ok\r\n"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn matching_etag_is_304() {
+        let root = root("etag");
+        let meta = fs::metadata(root.join("docs").join("a.css")).unwrap();
+        let etag = file_etag(&meta);
+        let req = format!(
+            "GET /docs/a.css HTTP/1.1\r\nHost: a\r\nIf-None-Match: {}\r\n\
+             If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n",
+            etag
+        );
+        let rsp = test::serve(StaticFiles::new(&root), req.as_bytes());
+        let rsp = String::from_utf8(rsp).unwrap();
+        assert!(rsp.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(rsp.contains(&format!("\r\nETag: {}\r\n", etag)));
+        assert!(rsp.ends_with("\r\n\r\n"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn other_methods_are_405() {
+        let root = root("method");
+        let req = b"DELETE /index.html HTTP/1.1\r\nHost: a\r\n\r\n";
+        let rsp = String::from_utf8(test::serve(StaticFiles::new(&root), req)).unwrap();
+        assert!(rsp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(rsp.contains("\r\nAllow: GET, HEAD\r\n"));
+        assert!(root.join("index.html").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+//! serving requests without a network, for testing a service
+
+use std::cell::RefCell;
+use std::io::{self, Cursor, Read, Write};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::context::ConnContext;
+use crate::http_server::{
+    each_connection_loop, HttpServer, HttpServerBuilder, HttpService, INLINE_HEADERS, MAX_BODY_SIZE,
+};
+use crate::request::{self, BodyState, ParseOptions, Request};
+use crate::transport::{Conn, Transport};
+
+/// a connection that reads the given bytes and keeps what's written
+struct MemoryStream {
+    input: Cursor<Vec<u8>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// the stream never blocks, so there's nothing to wait for
+impl Transport for MemoryStream {
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    #[cfg(unix)]
+    fn reset_io(&self) {}
+
+    #[cfg(unix)]
+    fn wait_io(&self) {}
+}
+
+/// serve the raw requests with the service and return the raw responses
+///
+/// the bytes are read like from a client that closes the connection after
+/// sending them, so pipelined requests get all their responses. it's the
+/// same loop the server runs, with the default settings
+///
+/// ```
+/// use std::io;
+/// use may_minihttp::{HttpService, Request, Response};
+///
+/// #[derive(Clone)]
+/// struct Hello;
+///
+/// impl HttpService for Hello {
+///     fn call(&mut self, _req: Request, rsp: &mut Response) -> io::Result<()> {
+///         rsp.body("hello");
+///         Ok(())
+///     }
+/// }
+///
+/// let rsp = may_minihttp::test::serve(Hello, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+/// let rsp = String::from_utf8(rsp).unwrap();
+/// assert!(rsp.starts_with("HTTP/1.1 200 Ok\r\n"));
+/// assert!(rsp.ends_with("\r\n\r\nhello"));
+/// ```
+///
+/// a malformed request gets a `400 Bad Request` after the responses of the
+/// ones before it, then the connection is closed
+///
+/// ```
+/// use may_minihttp::Router;
+///
+/// let router = Router::new().get("/", |_req, rsp| {
+///     rsp.body("hello");
+///     Ok(())
+/// });
+/// let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\ngarbage\r\n\r\n";
+/// let rsp = may_minihttp::test::serve(router, req);
+/// let rsp = String::from_utf8(rsp).unwrap();
+/// let (first, second) = rsp.split_once("hello").unwrap();
+/// assert!(first.starts_with("HTTP/1.1 200 Ok\r\n"));
+/// assert!(second.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+/// ```
+pub fn serve<T: HttpService>(service: T, requests: &[u8]) -> Vec<u8> {
+    serve_with(HttpServer(service).builder(), requests)
+}
+
+/// serve the raw requests like `serve`, with the settings of the server
+///
+/// ```
+/// use may_minihttp::{HttpServer, Router};
+///
+/// let router = Router::new().get("/", |_req, rsp| {
+///     rsp.body("hello");
+///     Ok(())
+/// });
+/// let server = HttpServer(router).max_headers(1);
+/// let req = b"GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n";
+/// let rsp = may_minihttp::test::serve_with(server, req);
+/// assert!(rsp.starts_with(b"HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+/// ```
+pub fn serve_with<T: HttpService>(server: HttpServerBuilder<T>, requests: &[u8]) -> Vec<u8> {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let stream = MemoryStream {
+        input: Cursor::new(requests.to_vec()),
+        output: output.clone(),
+    };
+    each_connection_loop(stream, server.service, &server.config, None);
+    output.take()
+}
+
+/// decode the request at the start of `buf` and pass it to `f`
+///
+/// there's no connection, the body is only what follows the head in `buf`.
+/// `None` is a request that's not complete, an error is one the server
+/// rejects. the limits are the default ones, it's meant for fuzzing the parser
+///
+/// ```
+/// let req = b"GET /a?b=c HTTP/1.1\r\nHost: a\r\n\r\n";
+/// let path = may_minihttp::test::decode(req, |req| req.path().to_owned());
+/// assert_eq!(path.unwrap().as_deref(), Some("/a?b=c"));
+/// ```
+pub fn decode<R>(buf: &[u8], f: impl FnOnce(Request) -> R) -> io::Result<Option<R>> {
+    let stream = RefCell::new(MemoryStream {
+        input: Cursor::new(Vec::new()),
+        output: Rc::default(),
+    });
+    let stream: &Conn = &stream;
+    let mut headers = [httparse::EMPTY_HEADER; INLINE_HEADERS];
+    let body_state = BodyState::default();
+    let mut context = ConnContext::default();
+    let req = request::decode(
+        buf,
+        &mut headers,
+        stream,
+        None,
+        ParseOptions {
+            max_body_size: MAX_BODY_SIZE,
+            strict_headers: true,
+            read_timeout: None,
+        },
+        &body_state,
+        &mut context,
+    )?;
+    Ok(req.map(f))
+}
+
+/// a client that reads the responses slowly
+#[cfg(test)]
+pub(crate) mod slow {
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::net::SocketAddr;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::http_server::{each_connection_loop, HttpServerBuilder, HttpService};
+    use crate::transport::Transport;
+
+    /// what the client does next
+    pub(crate) enum Step {
+        /// send the bytes, a read takes as much of them as it has room for
+        Send(Vec<u8>),
+        /// send nothing for a while, a read with a shorter timeout times out
+        Pause(Duration),
+    }
+
+    /// a client sending the steps and taking at most `max_write` bytes per
+    /// write, every other write of a non-blocking stream would block
+    pub(crate) struct SlowStream {
+        steps: VecDeque<Step>,
+        max_write: usize,
+        output: Rc<RefCell<Vec<u8>>>,
+        nonblocking: Cell<bool>,
+        read_timeout: Cell<Option<Duration>>,
+        /// `wait_io` waits for as long as the next read or write takes
+        waited: Cell<bool>,
+        blocked: bool,
+    }
+
+    impl SlowStream {
+        pub(crate) fn new(steps: impl IntoIterator<Item = Step>, max_write: usize) -> Self {
+            SlowStream {
+                steps: steps.into_iter().collect(),
+                max_write,
+                output: Rc::default(),
+                nonblocking: Cell::new(false),
+                read_timeout: Cell::new(None),
+                waited: Cell::new(false),
+                blocked: false,
+            }
+        }
+    }
+
+    impl Read for SlowStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            // a wait before is for this read
+            let waited = self.waited.take();
+            loop {
+                match self.steps.front_mut() {
+                    None => return Ok(0),
+                    Some(Step::Pause(pause)) => {
+                        let timed_out = match self.read_timeout.get() {
+                            _ if waited => false,
+                            _ if self.nonblocking.get() => true,
+                            Some(timeout) => timeout < *pause,
+                            None => false,
+                        };
+                        if timed_out {
+                            // a blocking read past its timeout too
+                            return Err(io::ErrorKind::WouldBlock.into());
+                        }
+                        self.steps.pop_front();
+                    }
+                    Some(Step::Send(data)) => {
+                        let n = data.len().min(buf.len());
+                        buf[..n].copy_from_slice(&data[..n]);
+                        data.drain(..n);
+                        if data.is_empty() {
+                            self.steps.pop_front();
+                        }
+                        return Ok(n);
+                    }
+                }
+            }
+        }
+    }
+
+    impl Write for SlowStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.nonblocking.get() && !self.waited.take() {
+                self.blocked = !self.blocked;
+                if self.blocked {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+            let n = buf.len().min(self.max_write);
+            self.output.borrow_mut().extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for SlowStream {
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            self.nonblocking.set(nonblocking);
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.read_timeout.set(timeout);
+            Ok(())
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+
+        #[cfg(unix)]
+        fn reset_io(&self) {}
+
+        #[cfg(unix)]
+        fn wait_io(&self) {
+            self.waited.set(true);
+        }
+    }
+
+    /// serve the connection with the server loop and return what's written
+    pub(crate) fn serve<T: HttpService>(
+        server: HttpServerBuilder<T>,
+        stream: SlowStream,
+    ) -> Vec<u8> {
+        let output = stream.output.clone();
+        each_connection_loop(stream, server.service, &server.config, None);
+        output.take()
+    }
+}
@@ -0,0 +1,95 @@
+//! https support on top of `rustls`
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use may::net::TcpStream;
+use rustls::{ServerConfig, ServerConnection, Stream};
+
+use crate::transport::Transport;
+
+/// a tls session over an accepted tcp connection
+pub(crate) struct TlsStream {
+    conn: ServerConnection,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    /// do the tls handshake on the accepted socket
+    ///
+    /// the handshake runs in blocking mode, parking the coroutine
+    /// until it's done or the read timeout expires
+    pub(crate) fn accept(mut sock: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let mut conn = ServerConnection::new(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        while conn.is_handshaking() {
+            if conn.complete_io(&mut sock)? == (0, 0) {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(TlsStream { conn, sock })
+    }
+}
+
+// a `WouldBlock` from the socket leaves the session state intact,
+// so the same call can be retried once the socket is ready again
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Stream::new(&mut self.conn, &mut self.sock).read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Stream::new(&mut self.conn, &mut self.sock).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Stream::new(&mut self.conn, &mut self.sock).flush()
+    }
+}
+
+impl Transport for TlsStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        self.sock.write_timeout()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    fn set_cork(&self, cork: bool) -> io::Result<()> {
+        Transport::set_cork(&self.sock, cork)
+    }
+
+    #[cfg(unix)]
+    fn reset_io(&self) {
+        self.sock.reset_io()
+    }
+
+    #[cfg(unix)]
+    fn wait_io(&self) {
+        self.sock.wait_io()
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.conn.complete_io(&mut self.sock);
+    }
+}
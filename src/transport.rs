@@ -0,0 +1,313 @@
+//! the streams that the http connections are served on
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(unix)]
+use may::io::WaitIo;
+use may::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use may::os::unix::net::{UnixListener, UnixStream};
+
+/// the connection stream used by the request/response loop
+///
+/// the `WaitIo` like methods are used to park the coroutine
+/// when the stream is in non-blocking mode. a custom stream, like an in
+/// memory one for tests, is served with `HttpServer::start_on`
+pub trait Transport: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// a stream without a write timeout can leave this out
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(None)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// hold back the partial packets while corked, uncorking sends them
+    ///
+    /// only a tcp socket on linux has `TCP_CORK`, the others leave this out
+    fn set_cork(&self, _cork: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn reset_io(&self);
+
+    #[cfg(unix)]
+    fn wait_io(&self);
+
+    /// the socket when the data is written to it as it is, so a file can be
+    /// sent with `sendfile`. a stream that encrypts the data like tls has none
+    #[cfg(unix)]
+    fn raw_socket(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        TcpStream::write_timeout(self)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_cork(&self, cork: bool) -> io::Result<()> {
+        crate::listener::set_cork(self, cork)
+    }
+
+    #[cfg(unix)]
+    fn reset_io(&self) {
+        WaitIo::reset_io(self)
+    }
+
+    #[cfg(unix)]
+    fn wait_io(&self) {
+        WaitIo::wait_io(self)
+    }
+
+    #[cfg(unix)]
+    fn raw_socket(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        UnixStream::write_timeout(self)
+    }
+
+    /// a unix socket peer has no ip address
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn reset_io(&self) {
+        WaitIo::reset_io(self)
+    }
+
+    fn wait_io(&self) {
+        WaitIo::wait_io(self)
+    }
+
+    fn raw_socket(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
+
+/// a listener the server accepts the connections of, see `HttpServer::start_on`
+pub trait Listener: Send + 'static {
+    type Stream: Transport + Send + 'static;
+
+    /// wait for the next connection, parking the coroutine
+    fn accept(&self) -> io::Result<Self::Stream>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+}
+
+/// the connection stream shared by the request body and the response
+pub(crate) type Conn = RefCell<dyn Transport>;
+
+/// whether the error is the client going away rather than a failure of the server
+///
+/// those are expected on any busy server, like an idle connection timing out
+/// or a client closing the connection in the middle of a response
+pub(crate) fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::TimedOut
+    )
+}
+
+/// read from the stream, parking the coroutine until some data is available
+///
+/// with a read timeout the wait is done by a blocking read so that the socket
+/// timeout applies, an expired timeout is reported as `TimedOut`
+pub(crate) fn read_wait<S: Transport + ?Sized>(
+    stream: &mut S,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    loop {
+        match stream.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            res => return res,
+        }
+
+        #[cfg(unix)]
+        if timeout.is_none() {
+            stream.wait_io();
+            stream.reset_io();
+            continue;
+        }
+
+        stream.set_nonblocking(false)?;
+        let res = stream.read(buf);
+        stream.set_nonblocking(true)?;
+        return match res {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "read timeout"))
+            }
+            res => res,
+        };
+    }
+}
+
+/// write the whole buffer, parking the coroutine while the stream is not writable
+///
+/// with a write timeout the wait is a blocking write so the socket timeout
+/// applies, running out of time is a `TimedOut`
+pub(crate) fn write_wait<S: Transport + ?Sized>(stream: &mut S, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match stream.write(buf) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                #[cfg(unix)]
+                if stream.write_timeout()?.is_none() {
+                    stream.wait_io();
+                    stream.reset_io();
+                    continue;
+                }
+                return blocking(stream, |s| s.write_all(buf));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// send `len` bytes of the file from `offset` to the socket with `sendfile`,
+/// parking the coroutine while the socket is not writable
+///
+/// the data doesn't go through a buffer. return the bytes sent so far with
+/// the error, the file ending early is an `UnexpectedEof`
+#[cfg(target_os = "linux")]
+pub(crate) fn sendfile_wait<S: Transport + ?Sized>(
+    stream: &S,
+    socket: RawFd,
+    file: &File,
+    offset: u64,
+    len: u64,
+) -> (u64, io::Result<()>) {
+    // a single call sends at most about 2 GiB
+    const MAX_COUNT: u64 = 0x7fff_f000;
+    let mut off = offset as libc::off_t;
+    let mut sent = 0;
+    while sent < len {
+        let count = (len - sent).min(MAX_COUNT) as usize;
+        let n = unsafe { libc::sendfile(socket, file.as_raw_fd(), &mut off, count) };
+        match n {
+            0 => return (sent, Err(io::ErrorKind::UnexpectedEof.into())),
+            n if n > 0 => sent += n as u64,
+            _ => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => {
+                        stream.wait_io();
+                        stream.reset_io();
+                    }
+                    io::ErrorKind::Interrupted => {}
+                    _ => return (sent, Err(err)),
+                }
+            }
+        }
+    }
+    (sent, Ok(()))
+}
+
+/// flush the data buffered in the transport, parking the coroutine until it's written
+pub(crate) fn flush_wait<S: Transport + ?Sized>(stream: &mut S) -> io::Result<()> {
+    loop {
+        match stream.flush() {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                #[cfg(unix)]
+                if stream.write_timeout()?.is_none() {
+                    stream.wait_io();
+                    stream.reset_io();
+                    continue;
+                }
+                return blocking(stream, |s| s.flush());
+            }
+            res => return res,
+        }
+    }
+}
+
+/// without `WaitIo`, or to honor the write timeout, the operation is done in
+/// blocking mode to park the coroutine
+fn blocking<S: Transport + ?Sized, T>(
+    stream: &mut S,
+    f: impl FnOnce(&mut S) -> io::Result<T>,
+) -> io::Result<T> {
+    stream.set_nonblocking(false)?;
+    let res = f(stream);
+    stream.set_nonblocking(true)?;
+    match res {
+        // a blocking write past its timeout
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "write timeout"))
+        }
+        res => res,
+    }
+}
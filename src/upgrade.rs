@@ -0,0 +1,99 @@
+//! handing the connection over to another protocol after `101 Switching Protocols`
+
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+
+use crate::transport::{flush_wait, read_wait, write_wait, Transport};
+
+/// the callback that takes over an upgraded connection
+pub(crate) type OnUpgrade = Box<dyn FnOnce(Upgraded)>;
+
+/// the connection after the switch to another protocol
+///
+/// it's passed to the callback given to `Response::upgrade`, the reads and
+/// writes park the coroutine like the ones of the http connection did
+pub struct Upgraded {
+    stream: Box<dyn Transport>,
+    /// the bytes the client sent after the upgrade request
+    buf: BytesMut,
+    timeout: Option<Duration>,
+}
+
+impl Upgraded {
+    pub(crate) fn new(stream: Box<dyn Transport>, buf: BytesMut) -> Self {
+        Upgraded {
+            stream,
+            buf,
+            timeout: None,
+        }
+    }
+
+    /// set the timeout of each read, an expired one is reported as `TimedOut`
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// the remote address of the connection
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            return read_wait(&mut *self.stream, buf, self.timeout);
+        }
+        let n = self.buf.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.advance(n);
+        Ok(n)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_wait(&mut *self.stream, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        flush_wait(&mut *self.stream)
+    }
+}
+
+/// the `Sec-WebSocket-Accept` value for a valid websocket handshake request
+#[cfg(feature = "websocket")]
+pub(crate) fn websocket_accept(req: &crate::Request) -> Option<String> {
+    use crate::request::has_token;
+
+    const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+    let valid = req.method() == crate::Method::Get
+        && req.parameters.version == Some(1)
+        && req
+            .header_all("upgrade")
+            .any(|v| has_token(v, b"websocket"))
+        && req
+            .header_all("connection")
+            .any(|v| has_token(v, b"upgrade"))
+        && req.header("sec-websocket-version") == Some(&b"13"[..]);
+    if !valid {
+        return None;
+    }
+    let key = req.header("sec-websocket-key")?;
+    let key = crate::request::trim(key);
+    if key.is_empty() {
+        return None;
+    }
+
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(key);
+    sha1.update(GUID.as_bytes());
+    Some(crate::base64::encode(&sha1.digest().bytes()))
+}
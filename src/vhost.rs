@@ -0,0 +1,107 @@
+//! serving several sites from one listener by the host of the request
+
+use std::io;
+
+use crate::http_server::HttpService;
+use crate::mount::MountedService;
+use crate::request::Request;
+use crate::response::Response;
+
+/// a service that passes each request to the service of its host
+///
+/// the host is compared case insensitively and without the port, see
+/// `Request::host`. a request for another host goes to the fallback service,
+/// or without one it's answered with `404 Not Found`
+///
+/// ```no_run
+/// use may_minihttp::{HttpServer, Router, StaticFiles, VirtualHost};
+///
+/// let api = Router::new().get("/users", |_, rsp| {
+///     rsp.text("[]");
+///     Ok(())
+/// });
+/// let app = VirtualHost::new()
+///     .host("api.example.com", api)
+///     .fallback(StaticFiles::new("./public"));
+/// HttpServer(app).start("127.0.0.1:8080").unwrap();
+/// ```
+#[derive(Default)]
+pub struct VirtualHost {
+    hosts: Vec<(String, Box<dyn MountedService>)>,
+    fallback: Option<Box<dyn MountedService>>,
+    /// the service of the current request, for `on_error`
+    current: Option<Option<usize>>,
+}
+
+impl Clone for VirtualHost {
+    fn clone(&self) -> Self {
+        VirtualHost {
+            hosts: self
+                .hosts
+                .iter()
+                .map(|(host, service)| (host.clone(), service.clone_box()))
+                .collect(),
+            fallback: self.fallback.as_ref().map(|s| s.clone_box()),
+            current: None,
+        }
+    }
+}
+
+impl VirtualHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// serve the requests for the host with the service
+    pub fn host<S>(mut self, host: &str, service: S) -> Self
+    where
+        S: HttpService + Clone + Send + Sync + 'static,
+    {
+        self.hosts.push((host.to_owned(), Box::new(service)));
+        self
+    }
+
+    /// serve the requests for any other host with the service
+    pub fn fallback<S>(mut self, service: S) -> Self
+    where
+        S: HttpService + Clone + Send + Sync + 'static,
+    {
+        self.fallback = Some(Box::new(service));
+        self
+    }
+
+    fn service(&mut self, current: Option<usize>) -> Option<&mut Box<dyn MountedService>> {
+        match current {
+            Some(i) => Some(&mut self.hosts[i].1),
+            None => self.fallback.as_mut(),
+        }
+    }
+}
+
+impl HttpService for VirtualHost {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
+        let current = req.host().and_then(|host| {
+            self.hosts
+                .iter()
+                .position(|(h, _)| h.eq_ignore_ascii_case(host))
+        });
+        self.current = Some(current);
+        match self.service(current) {
+            Some(service) => service.call(req, rsp),
+            None => {
+                rsp.status_code("404", "Not Found");
+                Ok(())
+            }
+        }
+    }
+
+    fn on_error(&mut self, e: &io::Error, rsp: &mut Response) {
+        match self.current.and_then(|current| self.service(current)) {
+            Some(service) => service.on_error(e, rsp),
+            None => {
+                rsp.status_code("500", "Internal Server Error");
+                rsp.body_mut().extend_from_slice(e.to_string().as_bytes());
+            }
+        }
+    }
+}
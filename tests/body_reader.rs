@@ -0,0 +1,95 @@
+use std::io::Read;
+
+use may_minihttp::{test, Router};
+
+/// answer the body read through `body_reader` in reads of at most 3 bytes,
+/// with the reads separated by `|`
+fn echo() -> Router {
+    Router::new()
+        .post("/", |mut req, rsp| {
+            let body = req.body_reader();
+            let mut parts = Vec::new();
+            let mut buf = [0; 3];
+            loop {
+                let n = body.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                parts.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+            }
+            rsp.body_vec(parts.join("|").into_bytes());
+            Ok(())
+        })
+        .post("/ignore", |_req, rsp| {
+            rsp.body("ignored");
+            Ok(())
+        })
+}
+
+fn serve(req: &[u8]) -> String {
+    String::from_utf8(test::serve(echo(), req)).unwrap()
+}
+
+#[test]
+fn length_body_is_read_in_parts() {
+    let rsp = serve(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 7\r\n\r\nabcdefgXYZ");
+    assert!(rsp.ends_with("\r\n\r\nabc|def|g"), "{rsp:?}");
+}
+
+#[test]
+fn chunked_body_is_decoded() {
+    let req = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+        4\r\nabcd\r\n2;ext=1\r\nef\r\n0\r\nX-Sum: 1\r\n\r\n";
+    let rsp = serve(req);
+    let (_, body) = rsp.split_once("\r\n\r\n").unwrap();
+    assert!(body.split('|').all(|part| part.len() <= 3), "{rsp:?}");
+    assert_eq!(body.replace('|', ""), "abcdef");
+}
+
+#[test]
+fn unread_body_is_drained_for_the_next_request() {
+    let req = b"POST /ignore HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello\
+        POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nok";
+    let rsp = serve(req);
+    let (first, second) = rsp.split_once("ignored").unwrap();
+    assert!(first.starts_with("HTTP/1.1 200 Ok\r\n"), "{rsp:?}");
+    assert!(second.ends_with("\r\n\r\nok"), "{rsp:?}");
+}
+
+#[test]
+fn large_unread_body_closes_the_connection() {
+    let body = vec![b'a'; 1024 * 1024];
+    let mut req = format!(
+        "POST /ignore HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    req.extend_from_slice(&body);
+    req.extend_from_slice(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nok");
+    let rsp = serve(&req);
+    assert!(rsp.contains("\r\nConnection: close\r\n"), "{rsp:?}");
+    assert!(rsp.ends_with("\r\n\r\nignored"), "{rsp:?}");
+}
+
+#[test]
+fn header_is_kept_across_the_body_reads() {
+    let router = Router::new().post("/", |mut req, rsp| {
+        let mut start = [0; 5];
+        req.body_reader().read_exact(&mut start)?;
+        // the header borrows the head buffer, not the request
+        let ty = req.header("content-type").unwrap();
+        let mut rest = Vec::new();
+        req.body_reader().read_to_end(&mut rest)?;
+        let mut body = ty.to_vec();
+        body.extend_from_slice(b"|");
+        body.extend_from_slice(&start);
+        body.extend_from_slice(b"|");
+        body.extend_from_slice(&rest);
+        rsp.body_vec(body);
+        Ok(())
+    });
+    let req = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Type: text/plain\r\n\
+        Content-Length: 11\r\n\r\nhello world";
+    let rsp = String::from_utf8(test::serve(router, req)).unwrap();
+    assert!(rsp.ends_with("\r\n\r\ntext/plain|hello| world"), "{rsp:?}");
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "compress")]
+
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use may_minihttp::{test, Router};
+
+fn app() -> Router {
+    Router::new()
+        .get("/", |_req, rsp| {
+            let mut body = rsp.stream_compressed();
+            body.write_all(b"hello ")?;
+            body.write_all(b"world")?;
+            body.finish()
+        })
+        .get("/png", |_req, rsp| {
+            rsp.add_header("Content-Type", "image/png");
+            let mut body = rsp.stream_compressed();
+            body.write_all(b"hello")?;
+            body.finish()
+        })
+}
+
+/// the head and the chunked body of the response to `GET path`
+fn get(path: &str, accept: Option<&str>) -> (String, Vec<u8>) {
+    let mut req = format!("GET {} HTTP/1.1\r\nHost: a\r\n", path);
+    if let Some(accept) = accept {
+        req.push_str(&format!("Accept-Encoding: {}\r\n", accept));
+    }
+    req.push_str("\r\n");
+    let rsp = test::serve(app(), req.as_bytes());
+    let end = rsp.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8(rsp[..end].to_vec()).unwrap();
+    (head, unchunk(&rsp[end..]))
+}
+
+fn unchunk(mut chunks: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let line = chunks.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = std::str::from_utf8(&chunks[..line]).unwrap();
+        let size = usize::from_str_radix(size, 16).unwrap();
+        chunks = &chunks[line + 2..];
+        if size == 0 {
+            assert_eq!(chunks, b"\r\n");
+            return body;
+        }
+        body.extend_from_slice(&chunks[..size]);
+        assert_eq!(&chunks[size..size + 2], b"\r\n");
+        chunks = &chunks[size + 2..];
+    }
+}
+
+#[test]
+fn stream_is_compressed() {
+    let (head, body) = get("/", Some("gzip"));
+    assert!(head.contains("\r\nContent-Encoding: gzip\r\n"), "{head:?}");
+    assert!(head.contains("\r\nVary: Accept-Encoding\r\n"), "{head:?}");
+    let mut text = String::new();
+    GzDecoder::new(&body[..]).read_to_string(&mut text).unwrap();
+    assert_eq!(text, "hello world");
+}
+
+#[test]
+fn stream_not_compressed_for_the_client_still_varies() {
+    let (head, body) = get("/", None);
+    assert!(!head.contains("Content-Encoding"), "{head:?}");
+    assert!(head.contains("\r\nVary: Accept-Encoding\r\n"), "{head:?}");
+    assert_eq!(body, b"hello world");
+}
+
+#[test]
+fn stream_of_a_compressed_type_does_not_vary() {
+    let (head, body) = get("/png", Some("gzip"));
+    assert!(!head.contains("Content-Encoding"), "{head:?}");
+    assert!(!head.contains("Vary"), "{head:?}");
+    assert_eq!(body, b"hello");
+}
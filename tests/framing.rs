@@ -0,0 +1,173 @@
+use std::io::Write;
+
+use may_minihttp::{test, DefaultStatus, HttpServer, Router};
+
+fn get(router: Router) -> String {
+    let rsp = test::serve(router, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+    String::from_utf8(rsp).unwrap()
+}
+
+/// the values of the header lines with the given name in the head of `rsp`
+fn headers<'a>(rsp: &'a str, name: &str) -> Vec<&'a str> {
+    let head = rsp.split("\r\n\r\n").next().unwrap();
+    head.split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(": "))
+        .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v)
+        .collect()
+}
+
+/// the response with its `Date` value replaced, to compare the bytes
+fn without_date(rsp: &str) -> String {
+    let start = rsp.find("\r\nDate: ").unwrap() + "\r\nDate: ".len();
+    let end = start + rsp[start..].find("\r\n").unwrap();
+    format!("{}<date>{}", &rsp[..start], &rsp[end..])
+}
+
+#[test]
+fn chunked_has_no_content_length() {
+    let rsp = get(Router::new().get("/", |_req, rsp| {
+        rsp.header("Content-Length: 5");
+        rsp.set_chunked();
+        rsp.body("hello");
+        Ok(())
+    }));
+    assert!(headers(&rsp, "content-length").is_empty());
+    assert_eq!(headers(&rsp, "transfer-encoding"), ["chunked"]);
+
+    let rsp = get(Router::new().get("/", |_req, rsp| {
+        rsp.add_header("Content-Length", "5");
+        let mut chunks = rsp.stream_chunked();
+        chunks.write_all(b"hello")?;
+        chunks.finish()
+    }));
+    assert!(headers(&rsp, "content-length").is_empty());
+    assert_eq!(headers(&rsp, "transfer-encoding"), ["chunked"]);
+}
+
+#[test]
+fn set_chunked_framing_bytes() {
+    let rsp = get(Router::new().get("/", |_req, rsp| {
+        rsp.set_chunked();
+        rsp.body("abcdefghijklmnopqrstuvwxyz");
+        Ok(())
+    }));
+    assert_eq!(
+        without_date(&rsp),
+        "HTTP/1.1 200 Ok\r\nServer: may\r\nDate: <date>\r\n\
+         Transfer-Encoding: chunked\r\n\r\n\
+         1a\r\nabcdefghijklmnopqrstuvwxyz\r\n0\r\n\r\n"
+    );
+}
+
+#[test]
+fn set_chunked_frames_each_body_mut_write() {
+    let rsp = get(Router::new().get("/", |_req, rsp| {
+        rsp.set_chunked();
+        rsp.body_mut().extend_from_slice(b"hello");
+        rsp.body_mut().extend_from_slice(&[b'a'; 300]);
+        // nothing written, no empty chunk that would end the body
+        rsp.body_mut();
+        rsp.body_mut().extend_from_slice(b"!");
+        Ok(())
+    }));
+    let body = format!("5\r\nhello\r\n12c\r\n{}\r\n1\r\n!\r\n", "a".repeat(300));
+    assert_eq!(
+        without_date(&rsp),
+        format!(
+            "HTTP/1.1 200 Ok\r\nServer: may\r\nDate: <date>\r\n\
+             Transfer-Encoding: chunked\r\n\r\n\
+             {body}0\r\n\r\n"
+        )
+    );
+}
+
+#[test]
+fn set_chunked_to_http10_has_a_length() {
+    let router = Router::new().get("/", |_req, rsp| {
+        rsp.set_chunked();
+        rsp.body_mut().extend_from_slice(b"hel");
+        rsp.body_mut().extend_from_slice(b"lo");
+        Ok(())
+    });
+    let rsp = test::serve(router, b"GET / HTTP/1.0\r\n\r\n");
+    let rsp = String::from_utf8(rsp).unwrap();
+    assert_eq!(headers(&rsp, "content-length"), ["5"]);
+    assert!(headers(&rsp, "transfer-encoding").is_empty());
+    assert!(rsp.ends_with("\r\n\r\nhello"), "{rsp:?}");
+}
+
+#[test]
+fn chunked_trailers_bytes() {
+    let rsp = get(Router::new().get("/", |_req, rsp| {
+        rsp.trailer("X-Checksum");
+        let mut chunks = rsp.stream_chunked();
+        chunks.write_all(b"hello")?;
+        chunks.write_all(&[b'a'; 300])?;
+        chunks.finish_with_trailers(&[("X-Checksum", "abc")])
+    }));
+    let body = format!("5\r\nhello\r\n12c\r\n{}\r\n", "a".repeat(300));
+    assert_eq!(
+        without_date(&rsp),
+        format!(
+            "HTTP/1.1 200 Ok\r\nServer: may\r\nDate: <date>\r\n\
+             Transfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\r\n\
+             {body}0\r\nX-Checksum: abc\r\n\r\n"
+        )
+    );
+}
+
+#[test]
+fn untouched_response_bytes() {
+    let rsp = get(Router::new().get("/", |_req, _rsp| Ok(())));
+    assert_eq!(
+        without_date(&rsp),
+        "HTTP/1.1 200 Ok\r\nServer: may\r\nDate: <date>\r\nContent-Length: 0\r\n\r\n"
+    );
+}
+
+#[test]
+fn untouched_response_with_no_content_bytes() {
+    let router = Router::new()
+        .get("/", |_req, _rsp| Ok(()))
+        .get("/body", |_req, rsp| {
+            rsp.body("hello");
+            Ok(())
+        });
+    let server = HttpServer(router).default_status(DefaultStatus::NoContent);
+    let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET /body HTTP/1.1\r\nHost: a\r\n\r\n";
+    let rsp = String::from_utf8(test::serve_with(server, req)).unwrap();
+    let (first, second) = rsp.split_at(rsp.find("HTTP/1.1 200").unwrap());
+    assert_eq!(
+        without_date(first),
+        "HTTP/1.1 204 No Content\r\nServer: may\r\nDate: <date>\r\n\r\n"
+    );
+    assert_eq!(
+        without_date(second),
+        "HTTP/1.1 200 Ok\r\nServer: may\r\nDate: <date>\r\nContent-Length: 5\r\n\r\nhello"
+    );
+}
+
+#[test]
+fn garbage_after_a_request_is_400_and_closes() {
+    let router = Router::new().get("/", |_req, rsp| {
+        rsp.body("hello");
+        Ok(())
+    });
+    let req =
+        b"GET / HTTP/1.1\r\nHost: a\r\n\r\n\x00garbage\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+    let rsp = String::from_utf8(test::serve(router, req)).unwrap();
+    let (first, second) = rsp.split_at(rsp.find("HTTP/1.1 400").unwrap());
+    assert_eq!(
+        without_date(first),
+        "HTTP/1.1 200 Ok\r\nServer: may\r\nDate: <date>\r\nContent-Length: 5\r\n\r\nhello"
+    );
+    assert!(
+        second.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+        "{rsp:?}"
+    );
+    assert_eq!(headers(second, "connection"), ["close"]);
+    // the request after the garbage is not answered
+    assert!(!second.contains("hello"), "{rsp:?}");
+}
@@ -0,0 +1,47 @@
+use may_minihttp::{test, Router};
+
+fn hello() -> Router {
+    Router::new().get("/", |_req, rsp| {
+        rsp.body("hello");
+        Ok(())
+    })
+}
+
+/// the heads of the responses, each one followed by its `hello` body
+fn heads(rsp: &[u8]) -> Vec<String> {
+    let rsp = String::from_utf8(rsp.to_vec()).unwrap();
+    let heads: Vec<_> = rsp
+        .split_terminator("\r\n\r\nhello")
+        .map(str::to_owned)
+        .collect();
+    assert!(rsp.ends_with("\r\n\r\nhello"), "{rsp:?}");
+    heads
+}
+
+#[test]
+fn http10_keep_alive_serves_pipelined_requests() {
+    let req = b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+        GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n";
+    let heads = heads(&test::serve(hello(), req));
+    assert_eq!(heads.len(), 2);
+    for head in heads {
+        assert!(head.contains("\r\nConnection: keep-alive"), "{head:?}");
+        assert!(head.contains("\r\nContent-Length: 5"), "{head:?}");
+    }
+}
+
+#[test]
+fn http10_closes_after_one_response() {
+    let req = b"GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n";
+    let heads = heads(&test::serve(hello(), req));
+    assert_eq!(heads.len(), 1);
+    assert!(!heads[0].contains("keep-alive"), "{:?}", heads[0]);
+}
+
+#[test]
+fn http10_keep_alive_ends_with_a_plain_request() {
+    let req = b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+        GET / HTTP/1.0\r\n\r\n\
+        GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+    assert_eq!(heads(&test::serve(hello(), req)).len(), 2);
+}
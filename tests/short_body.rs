@@ -0,0 +1,69 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use may_minihttp::{test, Request, Router};
+
+type Errors = Arc<Mutex<Vec<Option<io::ErrorKind>>>>;
+
+/// serve a request of `Content-Length: 10` that ends after 3 bytes of body,
+/// the handler keeps the kind of the error of reading the body with `read`
+fn serve_short(content_type: &str, read: fn(&mut Request) -> io::Result<()>) -> Errors {
+    let errors = Errors::default();
+    let seen = errors.clone();
+    let router = Router::new().post("/", move |mut req, _rsp| {
+        let res = read(&mut req);
+        seen.lock()
+            .unwrap()
+            .push(res.as_ref().err().map(|e| e.kind()));
+        res
+    });
+    let req = format!(
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Type: {content_type}\r\n\
+         Content-Length: 10\r\n\r\na=b"
+    );
+    let rsp = test::serve(router, req.as_bytes());
+    // the client is gone, nothing is sent to it
+    assert!(rsp.is_empty(), "{:?}", String::from_utf8_lossy(&rsp));
+    errors
+}
+
+fn assert_eof(errors: Errors) {
+    assert_eq!(
+        *errors.lock().unwrap(),
+        [Some(io::ErrorKind::UnexpectedEof)]
+    );
+}
+
+#[test]
+fn short_body_is_unexpected_eof() {
+    assert_eof(serve_short("text/plain", |req| req.body().map(drop)));
+}
+
+#[test]
+fn short_form_is_unexpected_eof() {
+    let form = "application/x-www-form-urlencoded";
+    assert_eof(serve_short(form, |req| req.form().map(drop)));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn short_json_is_unexpected_eof() {
+    let json = "application/json";
+    assert_eof(serve_short(json, |req| {
+        req.json::<serde_json::Value>().map(drop)
+    }));
+}
+
+#[test]
+fn eof_of_the_handler_is_answered() {
+    let router = Router::new().post("/", |mut req, _rsp| {
+        req.body()?;
+        Err(io::ErrorKind::UnexpectedEof.into())
+    });
+    let req = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\na=b";
+    let rsp = String::from_utf8(test::serve(router, req)).unwrap();
+    assert!(
+        rsp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{rsp:?}"
+    );
+}
@@ -0,0 +1,49 @@
+use may_minihttp::{test, Router};
+
+fn echo() -> Router {
+    Router::new().get("/", |req, rsp| {
+        let value = req.header("x-value").unwrap_or_default();
+        rsp.body_vec(value.to_vec());
+        Ok(())
+    })
+}
+
+fn serve_header(line: &[u8]) -> String {
+    let mut req = b"GET / HTTP/1.1\r\nHost: a\r\n".to_vec();
+    req.extend_from_slice(line);
+    req.extend_from_slice(b"\r\n\r\n");
+    String::from_utf8_lossy(&test::serve(echo(), &req)).into_owned()
+}
+
+#[test]
+fn nul_in_value_is_400() {
+    let rsp = serve_header(b"X-Value: a\0b");
+    assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{rsp:?}");
+}
+
+#[test]
+fn bare_cr_in_value_is_400() {
+    let rsp = serve_header(b"X-Value: a\rb");
+    assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{rsp:?}");
+}
+
+#[test]
+fn bare_lf_in_value_is_400() {
+    let rsp = serve_header(b"X-Value: a\nb");
+    assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{rsp:?}");
+}
+
+#[test]
+fn obs_fold_is_400() {
+    let rsp = serve_header(b"X-Value: a\r\n b");
+    assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{rsp:?}");
+    let rsp = serve_header(b"X-Value: a\r\n\tb");
+    assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{rsp:?}");
+}
+
+#[test]
+fn tabs_and_spaces_in_value_are_accepted() {
+    let rsp = serve_header(b"X-Value: a\tb c \t d");
+    assert!(rsp.starts_with("HTTP/1.1 200 Ok\r\n"), "{rsp:?}");
+    assert!(rsp.ends_with("\r\n\r\na\tb c \t d"), "{rsp:?}");
+}
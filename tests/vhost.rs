@@ -0,0 +1,55 @@
+use may_minihttp::{test, Router, VirtualHost};
+
+fn site(name: &'static str) -> Router {
+    Router::new().get("/", move |_req, rsp| {
+        rsp.body(name);
+        Ok(())
+    })
+}
+
+fn sites() -> VirtualHost {
+    VirtualHost::new()
+        .host("a.example", site("a"))
+        .host("b.example", site("b"))
+}
+
+fn get(service: VirtualHost, req: &str) -> String {
+    String::from_utf8(test::serve(service, req.as_bytes())).unwrap()
+}
+
+#[test]
+fn request_goes_to_the_service_of_its_host() {
+    let rsp = get(sites(), "GET / HTTP/1.1\r\nHost: a.example\r\n\r\n");
+    assert!(rsp.ends_with("\r\n\r\na"), "{rsp:?}");
+    let rsp = get(sites(), "GET / HTTP/1.1\r\nHost: B.Example:8080\r\n\r\n");
+    assert!(rsp.ends_with("\r\n\r\nb"), "{rsp:?}");
+}
+
+#[test]
+fn absolute_target_is_used_over_the_host_header() {
+    let req = "GET http://b.example/ HTTP/1.1\r\nHost: a.example\r\n\r\n";
+    assert!(get(sites(), req).ends_with("\r\n\r\nb"));
+}
+
+#[test]
+fn other_host_goes_to_the_fallback() {
+    let req = "GET / HTTP/1.1\r\nHost: c.example\r\n\r\n";
+    let rsp = get(sites(), req);
+    assert!(rsp.starts_with("HTTP/1.1 404 Not Found\r\n"), "{rsp:?}");
+    let rsp = get(sites().fallback(site("fallback")), req);
+    assert!(rsp.ends_with("\r\n\r\nfallback"), "{rsp:?}");
+}
+
+#[test]
+fn http11_without_a_single_host_is_400() {
+    let rsp = get(sites(), "GET / HTTP/1.1\r\n\r\n");
+    assert!(rsp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{rsp:?}");
+    let req = "GET / HTTP/1.1\r\nHost: a.example\r\nHost: b.example\r\n\r\n";
+    assert!(get(sites(), req).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[test]
+fn http10_may_leave_out_the_host() {
+    let rsp = get(sites().fallback(site("fallback")), "GET / HTTP/1.0\r\n\r\n");
+    assert!(rsp.ends_with("\r\n\r\nfallback"), "{rsp:?}");
+}